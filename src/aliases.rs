@@ -0,0 +1,244 @@
+use crate::io::*;
+use crate::permissions::*;
+use crate::restart::parse_duration;
+use crate::*;
+
+use std::{thread, time::Duration};
+
+/*
+    An alias runs its commands as if they had been written one after the
+    other, so they can be manager commands too. "$1", "$2"... are replaced
+    by the arguments of the alias and "$*" by all of them
+*/
+
+const WAIT_COMMAND: &str = "wait";
+const ALL_ARGUMENTS: &str = "$*";
+
+#[derive(Clone)]
+enum AliasStep {
+    Command(String),
+    Wait(u64),
+}
+
+#[derive(Clone)]
+pub struct Alias {
+    name: String,
+    steps: Vec<AliasStep>,
+}
+
+impl Alias {
+    pub fn new(name: &str) -> Alias {
+        Alias {
+            name: String::from(name),
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    //A command or "wait <time>"
+    pub fn push(&mut self, line: &str) -> Option<()> {
+        let step = match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            [] => return None,
+            [WAIT_COMMAND, time] => AliasStep::Wait(parse_duration(time)?),
+            [WAIT_COMMAND, ..] => return None,
+            _ => AliasStep::Command(String::from(line.trim())),
+        };
+        self.steps.push(step);
+        Some(())
+    }
+
+    //The highest "$n" of the commands
+    fn arguments(&self) -> usize {
+        self.steps
+            .iter()
+            .filter_map(|step| match step {
+                AliasStep::Command(c) => Some(c),
+                AliasStep::Wait(_) => None,
+            })
+            .flat_map(|c| c.split('$').skip(1))
+            .filter_map(|s| {
+                let digits: String = s.chars().take_while(char::is_ascii_digit).collect();
+                digits.parse::<usize>().ok()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn usage(&self) -> String {
+        let mut usage = self.name.clone();
+        for n in 1..=self.arguments() {
+            usage.push_str(&format!(" <{}>", n));
+        }
+        usage
+    }
+
+    fn expand(&self, args: &[&str]) -> GenericResult<Vec<AliasStep>> {
+        let needed = self.arguments();
+        if args.len() < needed {
+            return Err(format!("Usage: {}", self.usage()).into());
+        }
+        let steps = self
+            .steps
+            .iter()
+            .map(|step| match step {
+                AliasStep::Command(c) => AliasStep::Command(replace_arguments(c, args)),
+                AliasStep::Wait(t) => AliasStep::Wait(*t),
+            })
+            .collect();
+        Ok(steps)
+    }
+}
+
+//"$10" is the tenth argument, not the first one followed by a 0
+fn replace_arguments(command: &str, args: &[&str]) -> String {
+    let command = command.replace(ALL_ARGUMENTS, &args.join(" "));
+    let mut parts = command.split('$');
+    let mut result = String::from(parts.next().unwrap_or(""));
+    for part in parts {
+        let digits = part.chars().take_while(char::is_ascii_digit).count();
+        match part[..digits].parse::<usize>() {
+            Ok(n) if n >= 1 && n <= args.len() => {
+                result.push_str(args[n - 1]);
+                result.push_str(&part[digits..]);
+            }
+            _ => {
+                result.push('$');
+                result.push_str(part);
+            }
+        }
+    }
+    result
+}
+
+//Commands of an alias can't be other aliases, so they can't call each other forever
+pub fn check_aliases(aliases: &[Alias]) -> GenericResult<()> {
+    for alias in aliases {
+        if alias.name.starts_with('@') {
+            return Err(format!("Invalid alias name: {}", alias.name).into());
+        }
+        for step in &alias.steps {
+            if let AliasStep::Command(c) = step {
+                let command = c.split_whitespace().next().unwrap_or("");
+                if find_alias(aliases, command).is_some() {
+                    return Err(
+                        format!("The alias {} uses the alias {}", alias.name, command).into(),
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn find_alias<'a>(aliases: &'a [Alias], name: &str) -> Option<&'a Alias> {
+    aliases.iter().find(|a| a.name == name)
+}
+
+/*
+    The commands are sent back to the input of the server, waiting when the alias says so.
+    A Telegram user can only run the alias if every command of it is allowed for them
+*/
+pub fn run_alias(alias: &Alias, args: &[&str], user: Option<i64>, permissions: &Permissions) {
+    let out = get_output_sender();
+    let steps = match alias.expand(args) {
+        Ok(steps) => steps,
+        Err(e) => {
+            warnln!(out, "{}", e);
+            return;
+        }
+    };
+    let by = format!("alias {}", alias.name);
+    for step in &steps {
+        if let AliasStep::Command(c) = step {
+            if !permissions.allows(user, c, &by) {
+                return;
+            }
+        }
+    }
+    let input = get_input_sender();
+    thread::spawn(move || {
+        for step in steps {
+            match step {
+                AliasStep::Command(c) => {
+                    if input.send(InputPacket::Command(c + "\n", user)).is_err() {
+                        break;
+                    }
+                }
+                AliasStep::Wait(t) => thread::sleep(Duration::from_secs(t)),
+            }
+        }
+    });
+}
+
+//help: shows the aliases and their commands
+pub fn help_command(aliases: &[Alias]) {
+    let out = get_output_sender();
+    if aliases.is_empty() {
+        infoln!(
+            out,
+            "There are no aliases. Add them to the Aliases section of config.ini"
+        );
+        return;
+    }
+    let mut s = String::from("Aliases:");
+    for alias in aliases {
+        let steps: Vec<String> = alias
+            .steps
+            .iter()
+            .map(|step| match step {
+                AliasStep::Command(c) => c.clone(),
+                AliasStep::Wait(t) => format!("{} {}s", WAIT_COMMAND, t),
+            })
+            .collect();
+        s.push_str(&format!("\n{}: {}", alias.usage(), steps.join("; ")));
+    }
+    infoln!(out, "{}", s);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alias(name: &str, lines: &[&str]) -> Alias {
+        let mut alias = Alias::new(name);
+        for line in lines {
+            alias.push(line).unwrap();
+        }
+        alias
+    }
+
+    #[test]
+    fn replaces_the_arguments() {
+        let args: Vec<&str> = "a b c d e f g h i j".split(' ').collect();
+        assert_eq!(replace_arguments("give $1 diamond $2", &args), "give a diamond b");
+        assert_eq!(replace_arguments("say $10 $1$2", &args), "say j ab");
+        assert_eq!(replace_arguments("say $*", &args[..3]), "say a b c");
+        assert_eq!(replace_arguments("say $4 $0 $ costs $$", &args[..3]), "say $4 $0 $ costs $$");
+    }
+
+    #[test]
+    fn parses_the_steps() {
+        let mut heal = alias("heal", &["effect give $1 instant_health", "wait 2m", "say $2 healed $1"]);
+        assert!(heal.push("wait").is_none());
+        assert!(heal.push("wait soon").is_none());
+        assert!(heal.push("  ").is_none());
+        assert_eq!(heal.usage(), "heal <1> <2>");
+        assert!(heal.expand(&["Steve"]).is_err());
+        let steps = heal.expand(&["Steve", "Alex"]).unwrap();
+        assert!(matches!(&steps[0], AliasStep::Command(c) if c == "effect give Steve instant_health"));
+        assert!(matches!(steps[1], AliasStep::Wait(120)));
+        assert!(matches!(&steps[2], AliasStep::Command(c) if c == "say Alex healed Steve"));
+    }
+
+    #[test]
+    fn aliases_cant_use_aliases() {
+        let day = alias("day", &["time set day"]);
+        assert!(check_aliases(&[day.clone(), alias("morning", &["weather clear"])]).is_ok());
+        assert!(check_aliases(&[day.clone(), alias("morning", &["weather clear", "day"])]).is_err());
+        assert!(check_aliases(&[alias("@survival", &["say hi"])]).is_err());
+        assert!(find_alias(&[day], "day").is_some());
+    }
+}
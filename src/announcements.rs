@@ -0,0 +1,224 @@
+use crate::io::*;
+use crate::ping::*;
+use crate::server_handler::ServerHandler;
+use crate::*;
+
+use chrono::{Duration as ChronoDuration, Local, NaiveTime};
+use serde_json::{json, Value};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/*
+    Messages are broadcast in turn. The ticker only tells the main loop
+    when it's time for the next one, so messages can be changed at runtime
+*/
+
+//JSON objects and arrays are sent as they are. Anything else ("42", "true"...) is plain text
+fn component(message: &str) -> Value {
+    match serde_json::from_str(message) {
+        Ok(value @ Value::Object(_)) | Ok(value @ Value::Array(_)) => value,
+        _ => json!({ "text": message }),
+    }
+}
+
+pub fn announcement_command(message: &str) -> String {
+    format!("tellraw @a {}", component(message))
+}
+
+pub struct Announcer {
+    messages: Vec<String>,
+    next: usize,
+}
+
+impl Announcer {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            messages: config.announcements_messages.clone(),
+            next: 0,
+        }
+    }
+
+    //Nobody would read it if the server is empty
+    pub fn announce(&mut self, server: &mut ServerHandler, config: &Config) {
+        let out = get_output_sender();
+        if self.messages.is_empty() {
+            return;
+        }
+        match ping(&config.server_address) {
+            Ok(status) if status.online_players > 0 => (),
+            _ => return,
+        }
+        self.next %= self.messages.len();
+        let command = announcement_command(&self.messages[self.next]);
+        self.next += 1;
+        if let Err(e) = server.sendln(command.as_bytes()) {
+            errorln!(out, "Could not send the announcement: {}", e);
+        }
+    }
+
+    fn list(&self) {
+        let out = get_output_sender();
+        if self.messages.is_empty() {
+            infoln!(out, "There are no announcements");
+            return;
+        }
+        let mut s = String::from("Announcements:");
+        for (i, message) in self.messages.iter().enumerate() {
+            s.push_str(&format!("\n{}. {}", i + 1, message));
+        }
+        infoln!(out, "{}", s);
+    }
+
+    fn find(&self, number: &str) -> GenericResult<usize> {
+        match number.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= self.messages.len() => Ok(n - 1),
+            _ => Err(format!("There is no announcement {}", number).into()),
+        }
+    }
+
+    /*
+        announcements [list]: shows the messages
+        announcements add <message>: adds a message to the rotation
+        announcements remove <number>: removes a message
+        announcements preview <number>: shows the text and the command of a message
+
+        Changes are lost when the manager exits. Write them in config.ini to keep them
+    */
+    pub fn command(&mut self, args: &[&str]) {
+        let out = get_output_sender();
+        let result = match args {
+            [] | ["list"] => {
+                self.list();
+                Ok(())
+            }
+            ["add", message @ ..] if !message.is_empty() => {
+                self.messages.push(message.join(" "));
+                infoln!(out, "Announcement {} added", self.messages.len());
+                Ok(())
+            }
+            ["remove", number] => self.find(number).map(|i| {
+                let message = self.messages.remove(i);
+                if self.next > i {
+                    self.next -= 1;
+                }
+                infoln!(out, "Announcement removed: {}", message);
+            }),
+            ["preview", number] => self.find(number).map(|i| {
+                let message = &self.messages[i];
+                infoln!(
+                    out,
+                    "Players will see: {}\nCommand: {}",
+                    chat_to_text(&component(message)),
+                    announcement_command(message)
+                );
+            }),
+            _ => {
+                warnln!(
+                    out,
+                    "Usage: announcements [list | add <message> | remove <number> | preview <number>]"
+                );
+                Ok(())
+            }
+        };
+
+        if let Err(e) = result {
+            errorln!(out, "{}", e);
+        }
+    }
+}
+
+/********** Ticker **********/
+
+//Sends InputPacket::Announce every interval and at the given times of the day
+pub struct AnnouncementTicker {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl AnnouncementTicker {
+    pub fn start(config: &Config) -> Option<AnnouncementTicker> {
+        let interval = config.announcements_interval;
+        let times = config.announcements_times.clone();
+        if interval.is_none() && times.is_empty() {
+            return None;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_c = stop.clone();
+        let input = get_input_sender();
+        let handle = thread::spawn(move || {
+            let mut last = Instant::now();
+            loop {
+                let by_interval = interval
+                    .map(|i| (last + Duration::from_secs(i)).saturating_duration_since(Instant::now()));
+                let wait = match (by_interval, until_next_time(&times)) {
+                    (Some(a), Some(b)) => a.min(b),
+                    (a, b) => a.or(b).unwrap_or_default(),
+                };
+                let deadline = Instant::now() + wait;
+                while Instant::now() < deadline && !stop_c.load(Ordering::SeqCst) {
+                    thread::park_timeout(deadline.saturating_duration_since(Instant::now()));
+                }
+                if stop_c.load(Ordering::SeqCst) {
+                    break;
+                }
+                last = Instant::now();
+                if input.send(InputPacket::Announce).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(AnnouncementTicker { stop, handle })
+    }
+
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.thread().unpark();
+        self.handle.join().unwrap();
+    }
+}
+
+//Times that have just been announced are left for the next day
+fn until_next_time(times: &[NaiveTime]) -> Option<Duration> {
+    let now = Local::now().naive_local();
+    times
+        .iter()
+        .map(|t| {
+            let mut next = now.date().and_time(*t);
+            if next <= now + ChronoDuration::seconds(1) {
+                next += ChronoDuration::days(1);
+            }
+            (next - now).to_std().unwrap_or_default()
+        })
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_json_objects_and_arrays_are_components() {
+        assert_eq!(component("Vote for us!"), json!({"text": "Vote for us!"}));
+        assert_eq!(component("42"), json!({"text": "42"}));
+        assert_eq!(component("\"quoted\""), json!({"text": "\"quoted\""}));
+        assert_eq!(component("{\"text\": \"Hi\", \"color\": \"gold\"}"), json!({"text": "Hi", "color": "gold"}));
+        assert_eq!(component("[\"\", {\"text\": \"Hi\"}]"), json!(["", {"text": "Hi"}]));
+        assert_eq!(announcement_command("a \"b\""), "tellraw @a {\"text\":\"a \\\"b\\\"\"}");
+    }
+
+    #[test]
+    fn next_time_is_within_a_day() {
+        assert_eq!(until_next_time(&[]), None);
+        let now = Local::now().naive_local().time();
+        let soon = until_next_time(&[now + ChronoDuration::minutes(10), now + ChronoDuration::hours(5)]).unwrap();
+        assert!(soon <= Duration::from_secs(10 * 60) && soon > Duration::from_secs(9 * 60));
+        let tomorrow = until_next_time(&[now]).unwrap();
+        assert!(tomorrow > Duration::from_secs(23 * 3600) && tomorrow <= Duration::from_secs(24 * 3600));
+    }
+}
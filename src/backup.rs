@@ -1,9 +1,21 @@
+use crate::io::*;
+use crate::properties::world_path;
+use crate::versions::server_data_version;
+use crate::world::LevelData;
 use crate::*;
-use flate2::{write::GzEncoder, Compression};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde_json::{json, Value};
 use sha2::{Digest, Sha256};
-use std::{fs::File, fs::OpenOptions, io, path::Path};
+use std::{
+   fs,
+   fs::File,
+   fs::OpenOptions,
+   io,
+   path::{Component, Path, PathBuf},
+};
 
-pub fn backup(config: &Config) -> GenericResult<()> {
+//Returns the path of the backup
+pub fn backup(config: &Config) -> GenericResult<PathBuf> {
    let mut i: u8 = 0;
    loop {
       let file_path = config.generate_backup_name(i);
@@ -12,7 +24,12 @@ pub fn backup(config: &Config) -> GenericResult<()> {
             return Err(err.into());
          }
       } else {
-         return Ok(());
+         //The archive is usable anyway, only restores into older versions can't be refused
+         if let Err(e) = write_metadata(config, &file_path) {
+            let out = get_output_sender();
+            warnln!(out, "Could not write the metadata of the backup {}: {}", file_path.display(), e);
+         }
+         return Ok(file_path);
       }
       if i == 255 {
          return Err("Limit of 256 daily backups exceeded".into());
@@ -21,7 +38,7 @@ pub fn backup(config: &Config) -> GenericResult<()> {
    }
 }
 
-fn compress_directory(origin_path: &Path, destination_path: &Path) -> Result<(), std::io::Error> {
+pub fn compress_directory(origin_path: &Path, destination_path: &Path) -> Result<(), std::io::Error> {
    let tar_gz = OpenOptions::new()
       .write(true)
       .create_new(true)
@@ -31,10 +48,107 @@ fn compress_directory(origin_path: &Path, destination_path: &Path) -> Result<(),
    let name = Path::new(origin_path)
       .file_name()
       .expect("Encoding error in the path");
-   tar.append_dir_all(origin_path, name)?;
+   tar.append_dir_all(name, origin_path)?;
    return Ok(());
 }
 
+//Written next to every backup
+fn metadata_path(backup_path: &Path) -> PathBuf {
+   let mut path = backup_path.as_os_str().to_owned();
+   path.push(".json");
+   PathBuf::from(path)
+}
+
+fn write_metadata(config: &Config, backup_path: &Path) -> GenericResult<()> {
+   let level = LevelData::load(&world_path(config)).ok();
+   let metadata = json!({
+      "data_version": level.as_ref().and_then(|l| l.data_version),
+      "version": level.as_ref().and_then(|l| l.version.clone()),
+   });
+   fs::write(metadata_path(backup_path), metadata.to_string())?;
+   Ok(())
+}
+
+//Data version of the world in the backup, if it was recorded
+pub fn backup_data_version(backup_path: &Path) -> Option<i64> {
+   let content = fs::read_to_string(metadata_path(backup_path)).ok()?;
+   let metadata: Value = serde_json::from_str(&content).ok()?;
+   metadata["data_version"].as_i64()
+}
+
+/*
+   The backup is extracted next to the target, which is kept aside until
+   the extracted copy has replaced it. Worlds can't be loaded by versions
+   older than the one that saved them
+*/
+pub fn restore(config: &Config, backup_path: &Path) -> GenericResult<()> {
+   if let (Some(world), Some(server)) = (backup_data_version(backup_path), server_data_version(config)) {
+      if world > server {
+         return Err(format!(
+            "The backup has a world from a newer version (data version {}) than the server (data version {})",
+            world, server
+         )
+         .into());
+      }
+   }
+
+   let target = config.backups_target.as_path();
+   let extracted = sibling(target, ".extracting");
+   let aside = sibling(target, ".restoring");
+   if extracted.exists() {
+      fs::remove_dir_all(&extracted)?;
+   }
+   fs::create_dir_all(&extracted)?;
+   let result = File::open(backup_path)
+      .and_then(|f| tar::Archive::new(GzDecoder::new(f)).unpack(&extracted))
+      .map_err(GenericError::from)
+      .and_then(|_| {
+         archived_target(&extracted, target).ok_or_else(|| {
+            GenericError::from(format!("{} doesn't have {}", backup_path.display(), target.display()))
+         })
+      })
+      .and_then(|copy| {
+         if target.exists() {
+            fs::rename(target, &aside)?;
+         }
+         if let Err(e) = fs::rename(&copy, target) {
+            if aside.exists() {
+               fs::rename(&aside, target)?;
+            }
+            return Err(e.into());
+         }
+         Ok(())
+      });
+   let _ = fs::remove_dir_all(&extracted);
+   if result.is_ok() && aside.exists() {
+      fs::remove_dir_all(&aside)?;
+   }
+   result
+}
+
+//"/srv/server" -> "/srv/server.extracting"
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+   let mut sibling = path.as_os_str().to_owned();
+   sibling.push(suffix);
+   PathBuf::from(sibling)
+}
+
+/*
+   Archives have the target under its name. Older ones have it under the
+   path written in config.ini
+*/
+fn archived_target(extracted: &Path, target: &Path) -> Option<PathBuf> {
+   let relative: PathBuf = target
+      .components()
+      .filter(|c| matches!(c, Component::Normal(_)))
+      .collect();
+   [target.file_name().map(|n| extracted.join(n)), Some(extracted.join(relative))]
+      .iter()
+      .flatten()
+      .find(|p| p.is_dir())
+      .cloned()
+}
+
 pub fn compute_hash(f_path: &Path) -> GenericResult<Vec<u8>> {
    let mut file = File::open(f_path)?;
    let mut sha256 = Sha256::new();
@@ -1,15 +1,43 @@
-use server_manager::{config::Config, io::*, jobs::*, server_handler::*, *};
-use std::{path::Path, process};
+use server_manager::{
+   aliases::*, announcements::*, backup::backup, chat::tellraw_command, config::Config, crash::send_new_reports, datapacks::*, eula::*, idle::*, io::*,
+   jobs::*, lists::*, logs::*, maintenance::*, network::*, performance::*, permissions::*, plugins::*, properties::*, region::*, restart::*,
+   server_handler::*, versions::*, world::*, *,
+};
+use std::{
+   path::Path,
+   process,
+   sync::mpsc::{channel, Receiver, Sender},
+   thread,
+};
 
 const CONFIG_FILE: &str = "./config.ini";
+const SERVERS_COMMAND: &str = "servers";
+const NETWORK_COMMAND: &str = "network";
+const HELP_COMMAND: &str = "help";
+const STOP_COMMAND_NAME: &str = "stop";
+//Commands sent to every server when they have no @name. The rest need it if there are several servers
+const BROADCAST_COMMANDS: [&str; 9] = [
+   "say", "tellraw", "title", "list", "save-all", "status", "perf", "versions", "backup",
+];
 
+/*
+   Every server is managed by its own thread. The main thread reads the
+   input, sends commands addressed with @name to that server (and the rest
+   to every server) and exits once no server is managed
+*/
 fn main() {
-   let config = Config::new(&Path::new(CONFIG_FILE)).unwrap_or_else(|e| {
+   let mut servers = Config::load_servers(&Path::new(CONFIG_FILE)).unwrap_or_else(|e| {
       println!("Error reading the configuration: {}", e);
       process::exit(-1);
    });
+   for config in servers.iter_mut() {
+      if let Err(e) = load_active_version(config) {
+         println!("Error loading the active server version of {}: {}", config.name, e);
+         process::exit(-1);
+      }
+   }
 
-   let jobs_handler = JobManager::start_jobs(&config);
+   let jobs_handler = JobManager::start_jobs(&servers);
 
    let out = get_output_sender();
    let input = get_input_receiver();
@@ -18,42 +46,548 @@ fn main() {
       out,
       "Server manager jobs have started. Starting Minecraft Server UwU. New fresh version"
    );
+   prefix_output(servers.len() > 1);
 
-   let mut handler = match ServerHandler::start_server(&config) {
-      Ok(s) => s,
-      Err(err) => {
-         errorln!(out, "Error starting the server: {}", err);
-         jobs_handler.terminate_jobs();
-         process::exit(-1);
+   for config in &servers {
+      if let Some(time) = config.restart_daily_time {
+         with_server(&config.name, || schedule_daily_restart(time, config));
+      }
+   }
+   let single = servers.len() == 1;
+   let (proxies, backends): (Vec<Config>, Vec<Config>) =
+      servers.into_iter().partition(|c| c.role == ServerRole::Proxy);
+   if !proxies.is_empty() {
+      start_proxies_when_ready(&backends, &proxies);
+   }
+   let mut workers: Vec<Worker> = backends.into_iter().map(Worker::start).collect();
+   workers.extend(proxies.into_iter().map(Worker::new));
+
+   loop {
+      match input.recv().unwrap() {
+         (Some(name), InputPacket::ServerExited) => {
+            if let Some(worker) = workers.iter_mut().find(|w| w.config.name == name) {
+               if let Err(err) = worker.join() {
+                  if single {
+                     exit_with_error(jobs_handler, err);
+                  }
+                  errorln!(out, "{} stopped: {}", name, err);
+               }
+            }
+            if workers.iter().all(|w| !w.is_running()) {
+               break;
+            }
+         }
+         (Some(name), packet) => {
+            if let Some(worker) = workers.iter().find(|w| w.config.name == name) {
+               worker.send(packet);
+            }
+         }
+         (None, InputPacket::Command(s, user)) => route_command(&mut workers, &s, user),
+         (None, packet) => {
+            for worker in &workers {
+               worker.send(packet.clone());
+            }
+         }
+      }
+   }
+
+   infoln!(out, "Exiting...");
+   jobs_handler.terminate_jobs();
+}
+
+struct Worker {
+   config: Config,
+   sender: Option<Sender<InputPacket>>,
+   handle: Option<thread::JoinHandle<GenericResult<()>>>,
+}
+
+impl Worker {
+   fn new(config: Config) -> Worker {
+      Worker {
+         config,
+         sender: None,
+         handle: None,
+      }
+   }
+
+   fn start(config: Config) -> Worker {
+      let mut worker = Worker::new(config);
+      worker.spawn();
+      worker
+   }
+
+   //The jar could have been upgraded since the configuration was loaded
+   fn spawn(&mut self) {
+      let (sender, receiver) = channel();
+      let mut config = self.config.clone();
+      self.sender = Some(sender);
+      self.handle = Some(thread::spawn(move || {
+         set_thread_server(Some(&config.name));
+         let result = load_active_version(&mut config).and_then(|_| run_server(config, receiver));
+         get_input_sender().send(InputPacket::ServerExited).unwrap();
+         result
+      }));
+   }
+
+   fn is_running(&self) -> bool {
+      self.sender.is_some()
+   }
+
+   fn send(&self, packet: InputPacket) {
+      if let Some(sender) = &self.sender {
+         let _ = sender.send(packet);
+      }
+   }
+
+   //Returns the error the server thread ended with
+   fn join(&mut self) -> GenericResult<()> {
+      self.sender = None;
+      match self.handle.take() {
+         Some(handle) => handle.join().unwrap(),
+         None => Ok(()),
+      }
+   }
+}
+
+//"@survival backup" only goes to survival. A stopped server can be managed again with "@name start"
+fn route_command(workers: &mut [Worker], s: &str, user: Option<i64>) {
+   let out = get_output_sender();
+   let command = s.trim_start();
+   let address = match command.strip_prefix('@') {
+      Some(address) => address,
+      None if command.trim() == SERVERS_COMMAND => {
+         let names: Vec<String> = workers
+            .iter()
+            .map(|w| match w.is_running() {
+               true => w.config.name.clone(),
+               false => format!("{} (stopped)", w.config.name),
+            })
+            .collect();
+         infoln!(out, "Servers: {}", names.join(", "));
+         return;
       }
+      //Every server has the same aliases
+      None if command.trim() == HELP_COMMAND => {
+         help_command(&workers[0].config.aliases);
+         return;
+      }
+      None if command.trim() == NETWORK_COMMAND => {
+         let configs: Vec<&Config> = workers.iter().map(|w| &w.config).collect();
+         network_command(&configs);
+         return;
+      }
+      //Proxies are stopped before the backends
+      None if command.trim() == STOP_COMMAND_NAME => {
+         for worker in workers.iter_mut().filter(|w| w.config.role == ServerRole::Proxy && w.is_running()) {
+            worker.send(InputPacket::Command(String::from(s), user));
+            if let Err(err) = worker.join() {
+               errorln!(out, "{} stopped: {}", worker.config.name, err);
+            }
+         }
+         for worker in workers.iter() {
+            worker.send(InputPacket::Command(String::from(s), user));
+         }
+         return;
+      }
+      None if workers.len() > 1 && !command.trim().is_empty() => {
+         let name = command.split_whitespace().next().unwrap_or("");
+         if BROADCAST_COMMANDS.contains(&name) {
+            for worker in workers.iter() {
+               worker.send(InputPacket::Command(String::from(s), user));
+            }
+         } else {
+            let names: Vec<&str> = workers.iter().map(|w| w.config.name.as_str()).collect();
+            warnln!(
+               out,
+               "There are several servers. Choose one with @name {} ({})",
+               command.trim(),
+               names.join(", ")
+            );
+         }
+         return;
+      }
+      None => {
+         for worker in workers.iter() {
+            worker.send(InputPacket::Command(String::from(s), user));
+         }
+         return;
+      }
+   };
+   let (name, rest) = address.split_at(address.find(char::is_whitespace).unwrap_or(address.len()));
+   let rest = rest.trim_start_matches([' ', '\t']);
+   match workers.iter_mut().find(|w| w.config.name == name) {
+      None => {
+         warnln!(out, "There is no server called {}", name);
+      }
+      Some(_) if rest.trim().is_empty() => {
+         warnln!(out, "Usage: @{} <command>", name);
+      }
+      Some(worker) if !worker.is_running() && rest.trim() == START_COMMAND => worker.spawn(),
+      Some(worker) if !worker.is_running() => {
+         warnln!(out, "{} is stopped. Use @{} start to start it", name, name);
+      }
+      Some(worker) => worker.send(InputPacket::Command(String::from(rest), user)),
+   }
+}
+
+fn run_server(mut config: Config, input: Receiver<InputPacket>) -> GenericResult<()> {
+   let out = get_output_sender();
+
+   //None while the server is stopped
+   let mut server = if config.control == ControlMode::Stdin && in_maintenance(&config.server_path()) {
+      warnln!(out, "Maintenance mode is on. Use the start command to start the server");
+      None
+   } else if can_start(&config) {
+      Some(ServerHandler::start_server(&config)?)
+   } else {
+      notify_eula();
+      None
    };
+   let mut idle_monitor = match server {
+      Some(_) => config.idle_timeout.map(|t| IdleMonitor::start(&config, t)),
+      None => None,
+   };
+   let mut wake_listener: Option<WakeListener> = None;
+   let mut countdown: Option<RestartCountdown> = None;
+
+   let mut performance = PerformanceMonitor::new(&config);
+   let performance_ticker = config.performance_interval.map(PerformanceTicker::start);
+
+   let mut announcer = Announcer::new(&config);
+   let announcement_ticker = AnnouncementTicker::start(&config);
+
+   let result = 'main: loop {
+      let s = match input.recv() {
+         Err(_) => break 'main Ok(()),
+         Ok(packet) => packet,
+      };
+      let (s, user) = match s {
+         InputPacket::Command(s, user) => (s, user),
+         //The server could have been replaced or left stopped since then
+         InputPacket::ServerDied if server.as_mut().map(|h| h.is_running()).unwrap_or(true) => continue,
+         InputPacket::ServerDied => {
+            errorln!(out, "Server went brrr");
+            //Sent now, as the manager could exit right after
+            send_new_reports(&config.server_path(), &out, true);
+            let eula_problem = server
+               .take()
+               .map(|h| h.eula_required())
+               .unwrap_or(false)
+               || !can_start(&config);
+            if !eula_problem {
+               break 'main Err("The server process died unexpectedly".into());
+            }
+            if let Some(m) = idle_monitor.take() {
+               m.stop();
+            }
+            notify_eula();
+            continue;
+         }
+         InputPacket::Lag { ms, ticks } => {
+            performance.record_lag(ms, ticks);
+            continue;
+         }
+         InputPacket::PerformanceSample => {
+            if let Some(handler) = server.as_mut() {
+               performance.sample(handler);
+            }
+            continue;
+         }
+         InputPacket::TpsAnswer(answer) => {
+            performance.answer(server.as_mut(), answer);
+            continue;
+         }
+         InputPacket::Announce => {
+            if let Some(handler) = server.as_mut() {
+               announcer.announce(handler, &config);
+            }
+            continue;
+         }
+         InputPacket::ServerExited => continue,
+         InputPacket::Chat { user, text } => {
+            if let Some(handler) = server.as_mut() {
+               if let Err(e) = handler.sendln(tellraw_command(&user, &text).as_bytes()) {
+                  errorln!(out, "Could not send the chat message to the game: {}", e);
+               }
+            }
+            continue;
+         }
+      };
+      //Commands of the aliases are checked too, as they come back with the user that ran them
+      if let Some(user) = user {
+         if let Err(e) = config.telegram_permissions.check(user, &s) {
+            report_denied(user, &e);
+            continue;
+         }
+      }
+      let mut args = s.split_whitespace();
+      let command = args.next().unwrap_or("");
+
+      if let Some(alias) = find_alias(&config.aliases, command) {
+         let args: Vec<&str> = args.collect();
+         run_alias(alias, &args, user, &config.telegram_permissions);
+         continue;
+      }
 
-   'main: loop {
-      let s = input.recv().unwrap();
-      match s.as_str().trim() {
-         "stop" => {
+      match (command, server.take()) {
+         ("stop", handler) => {
+            if let Some(handler) = handler {
+               handler.stop_server();
+            }
+            if let Some(w) = wake_listener.take() {
+               w.stop();
+            }
+            break 'main Ok(());
+         }
+         (SLEEP_COMMAND, Some(handler)) if config.control == ControlMode::Rcon => {
+            warnln!(out, "Only servers spawned by the manager can sleep");
+            server = Some(handler);
+         }
+         (SLEEP_COMMAND, Some(handler)) => {
+            infoln!(out, "Stopping the server until someone tries to join");
+            if let Some(m) = idle_monitor.take() {
+               m.stop();
+            }
+            if let Some(c) = countdown.take() {
+               c.cancel();
+            }
             handler.stop_server();
-            break 'main;
+            wake_listener = if in_maintenance(&config.server_path()) {
+               infoln!(out, "Maintenance mode is on. Use the start command to start the server");
+               None
+            } else {
+               match WakeListener::start(&config) {
+                  Ok(w) => Some(w),
+                  Err(e) => {
+                     errorln!(out, "Could not listen for players. Use the start command to start the server: {}", e);
+                     None
+                  }
+               }
+            };
+         }
+         ("eula", handler) => {
+            let args: Vec<&str> = args.collect();
+            match args.as_slice() {
+               [] if eula_accepted(&config) => {
+                  infoln!(out, "The Minecraft EULA has been accepted");
+               }
+               [] => notify_eula(),
+               ["accept"] => match accept_eula(&config) {
+                  Ok(_) => {
+                     infoln!(out, "Minecraft EULA accepted ({})", EULA_URL);
+                     if handler.is_none() && wake_listener.is_none() && !in_maintenance(&config.server_path()) {
+                        get_input_sender()
+                           .send(InputPacket::Command(String::from(START_COMMAND), None))
+                           .unwrap();
+                     }
+                  }
+                  Err(e) => {
+                     errorln!(out, "Could not write {}: {}", EULA_FILE, e);
+                  }
+               },
+               _ => {
+                  warnln!(out, "Usage: eula [accept]");
+               }
+            }
+            server = handler;
+         }
+         (START_COMMAND, None) if !can_start(&config) => notify_eula(),
+         (START_COMMAND, None) => {
+            if let Some(w) = wake_listener.take() {
+               w.stop();
+            }
+            server = match ServerHandler::start_server(&config) {
+               Ok(s) => Some(s),
+               Err(err) => break 'main Err(err),
+            };
+            idle_monitor = config.idle_timeout.map(|t| IdleMonitor::start(&config, t));
+         }
+         ("announcements", handler) => {
+            let args: Vec<&str> = args.collect();
+            announcer.command(&args);
+            server = handler;
+         }
+         ("perf", handler) => {
+            performance.report();
+            server = handler;
+         }
+         ("versions", handler) => {
+            versions_command(&config);
+            server = handler;
+         }
+         ("datapacks", mut handler) => {
+            let args: Vec<&str> = args.collect();
+            datapacks_command(&args, &config, handler.as_mut(), user);
+            server = handler;
          }
-         "backup" => {
-            handler.backup(&config);
-            handler = match ServerHandler::start_server(&config) {
+         ("regions", handler) => {
+            let args: Vec<&str> = args.collect();
+            regions_command(&args, &config, handler.is_some());
+            server = handler;
+         }
+         ("logs", handler) => {
+            let args: Vec<&str> = args.collect();
+            logs_command(&args, &config);
+            server = handler;
+         }
+         ("world", handler) => {
+            let args: Vec<&str> = args.collect();
+            server = match world_command(&args, &config, handler) {
                Ok(s) => s,
-               Err(err) => {
-                  errorln!(out, "Error starting the server: {}", err);
-                  jobs_handler.terminate_jobs();
-                  process::exit(-1);
+               Err(err) => break 'main Err(err),
+            };
+         }
+         ("maintenance", mut handler) => {
+            let args: Vec<&str> = args.collect();
+            maintenance_command(&args, &config, handler.as_mut());
+            if in_maintenance(&config.server_path()) {
+               if let Some(c) = countdown.take() {
+                  c.cancel();
                }
+               if let Some(w) = wake_listener.take() {
+                  w.stop();
+               }
+            }
+            server = handler;
+         }
+         ("props", mut handler) => {
+            let args: Vec<&str> = args.collect();
+            properties_command(&args, &config, handler.as_mut(), user);
+            server = handler;
+         }
+         (command, handler) if AddonFolder::from_command(command).is_some() => {
+            let args: Vec<&str> = args.collect();
+            addons_command(AddonFolder::from_command(command).unwrap(), &args, &config);
+            server = handler;
+         }
+         (command, mut handler) if PlayerList::from_command(command).is_some() => {
+            let args: Vec<&str> = args.collect();
+            let list = PlayerList::from_command(command).unwrap();
+            list_command(list, &args, &config, handler.as_mut(), user);
+            server = handler;
+         }
+         ("backup", None) => {
+            //Nothing is writing the world while the server sleeps
+            infoln!(out, "Creating backup...");
+            if let Err(e) = backup(&config) {
+               errorln!(out, "Could not make backup. Error: {}", e);
             }
          }
-         _ => {
+         ("status", None) if wake_listener.is_some() => {
+            infoln!(out, "Server is sleeping until someone tries to join");
+         }
+         ("status", None) => {
+            infoln!(out, "Server is stopped");
+         }
+         (_, None) => {
+            warnln!(out, "Server is stopped. Use the start command to start it");
+         }
+         (START_COMMAND, Some(handler)) => {
+            warnln!(out, "Server is already running");
+            server = Some(handler);
+         }
+         ("status", Some(mut handler)) => {
+            handler.status(&config);
+            server = Some(handler);
+         }
+         ("backup", Some(handler)) => {
+            server = match handler.backup(&config) {
+               Ok(s) => Some(s),
+               Err(err) => break 'main Err(err),
+            }
+         }
+         ("upgrade", Some(handler)) if config.control == ControlMode::Rcon => {
+            warnln!(out, "Only servers spawned by the manager can be upgraded");
+            server = Some(handler);
+         }
+         ("upgrade", Some(handler)) => {
+            let args: Vec<&str> = args.collect();
+            server = match args.as_slice() {
+               [version] => upgrade(handler, version, &mut config),
+               _ => {
+                  warnln!(out, "Usage: upgrade <version>");
+                  Some(handler)
+               }
+            };
+         }
+         ("restart", Some(mut handler)) => {
+            let args: Vec<&str> = args.collect();
+            match args.first() {
+               None | Some(&"now") => {
+                  if let Some(c) = countdown.take() {
+                     c.cancel();
+                  }
+                  handler = match handler.restart(&config) {
+                     Ok(s) => s,
+                     Err(err) => break 'main Err(err),
+                  }
+               }
+               Some(&"cancel") => match countdown.take() {
+                  Some(c) => {
+                     c.cancel();
+                     infoln!(out, "Restart cancelled");
+                     handler.sendln(b"say Restart cancelled").unwrap_or_else(|err| {
+                        errorln!(out, "Error: {}", err);
+                     });
+                  }
+                  None => {
+                     warnln!(out, "There is no restart to cancel");
+                  }
+               },
+               Some(delay) => match parse_duration(delay) {
+                  Some(delay) => {
+                     let reason = args[1..].join(" ");
+                     if let Some(c) = countdown.replace(RestartCountdown::start(delay, &reason, &config)) {
+                        c.cancel();
+                     }
+                     infoln!(out, "Server will restart in {}", format_duration(delay));
+                  }
+                  None => {
+                     warnln!(
+                        out,
+                        "Invalid delay {}. Usage: restart [now | cancel | <delay> [reason]]",
+                        delay
+                     );
+                  }
+               },
+            }
+            server = Some(handler);
+         }
+         (_, Some(mut handler)) => {
             handler.send(s.as_bytes()).unwrap_or_else(|err| {
                errorln!(out, "Error: {}", err);
             });
+            server = Some(handler);
          }
       }
+   };
+
+   if let Some(m) = idle_monitor {
+      m.stop();
+   }
+   if let Some(w) = wake_listener {
+      w.stop();
    }
+   if let Some(c) = countdown {
+      c.cancel();
+   }
+   if let Some(t) = performance_ticker {
+      t.stop();
+   }
+   if let Some(t) = announcement_ticker {
+      t.stop();
+   }
+   result
+}
 
-   infoln!(out, "Exiting...");
+//Servers spawned by the manager refuse to start without the EULA. Proxies don't have one
+fn can_start(config: &Config) -> bool {
+   config.control == ControlMode::Rcon || config.role == ServerRole::Proxy || eula_accepted(config)
+}
+
+fn exit_with_error(jobs_handler: JobManager, err: GenericError) -> ! {
+   let out = get_output_sender();
+   errorln!(out, "Error starting the server: {}", err);
    jobs_handler.terminate_jobs();
+   process::exit(-1);
 }
@@ -0,0 +1,181 @@
+use crate::ping::strip_formatting;
+
+use serde_json::json;
+use std::collections::HashSet;
+
+/*
+    Chat bridge: the chat, joins, leaves and deaths of the server log are
+    sent to a Telegram chat, and the messages of that chat are shown in
+    the game with tellraw
+*/
+
+//Vanilla "[12:00:00] [Server thread/INFO]: ", Paper "[12:00:00 INFO]: "
+//and Forge "[12:00:00] [Server thread/INFO] [minecraft/DedicatedServer]: "
+const INFO_MARKER: &str = "INFO]";
+const MESSAGE_MARKER: &str = "]: ";
+//Added by 1.19+ servers to messages without a signature
+const NOT_SECURE_PREFIX: &str = "[Not Secure] ";
+const JOINED_SUFFIX: &str = " joined the game";
+const LEFT_SUFFIX: &str = " left the game";
+//Beginnings of the vanilla death messages, after the name of the player
+const DEATH_PHRASES: [&str; 20] = [
+    "was ",
+    "walked into",
+    "drowned",
+    "died",
+    "experienced kinetic energy",
+    "blew up",
+    "hit the ground too hard",
+    "fell ",
+    "went up in flames",
+    "burned to death",
+    "went off with a bang",
+    "tried to swim in lava",
+    "discovered the floor was lava",
+    "suffocated",
+    "starved to death",
+    "left the confines of this world",
+    "didn't want to live",
+    "withered away",
+    "froze to death",
+    "got finished off",
+];
+const TELEGRAM_TAG: &str = "[Telegram] ";
+
+pub enum ChatEvent {
+    Message { player: String, text: String },
+    Joined(String),
+    Left(String),
+    Death(String),
+}
+
+impl ChatEvent {
+    pub fn format(&self) -> String {
+        match self {
+            ChatEvent::Message { player, text } => format!("{}: {}", player, text),
+            ChatEvent::Joined(player) => format!("{} joined the game", player),
+            ChatEvent::Left(player) => format!("{} left the game", player),
+            ChatEvent::Death(message) => message.clone(),
+        }
+    }
+}
+
+//Death messages are only recognized for players that are online
+pub struct ChatParser {
+    online: HashSet<String>,
+}
+
+impl ChatParser {
+    pub fn new() -> Self {
+        Self {
+            online: HashSet::new(),
+        }
+    }
+
+    pub fn parse(&mut self, line: &str) -> Option<ChatEvent> {
+        let message = strip_formatting(log_message(line)?);
+        let message = message.trim_start_matches(NOT_SECURE_PREFIX);
+
+        if let Some(rest) = message.strip_prefix('<') {
+            let (player, text) = rest.split_once("> ")?;
+            return Some(ChatEvent::Message {
+                player: String::from(player),
+                text: String::from(text),
+            });
+        }
+        if let Some(player) = message.strip_suffix(JOINED_SUFFIX).filter(|p| is_name(p)) {
+            self.online.insert(String::from(player));
+            return Some(ChatEvent::Joined(String::from(player)));
+        }
+        if let Some(player) = message.strip_suffix(LEFT_SUFFIX).filter(|p| is_name(p)) {
+            self.online.remove(player);
+            return Some(ChatEvent::Left(String::from(player)));
+        }
+        let (player, rest) = message.split_once(' ')?;
+        if self.online.contains(player) && DEATH_PHRASES.iter().any(|p| rest.starts_with(p)) {
+            return Some(ChatEvent::Death(String::from(message)));
+        }
+        None
+    }
+}
+
+impl Default for ChatParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//Text of an info line of the log, without the time and thread
+fn log_message(line: &str) -> Option<&str> {
+    let info = line.find(INFO_MARKER)?;
+    let start = info + line[info..].find(MESSAGE_MARKER)? + MESSAGE_MARKER.len();
+    Some(line[start..].trim_end())
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(' ')
+}
+
+//The text is escaped by serde_json, so it can't break out of the JSON component
+pub fn tellraw_command(user: &str, text: &str) -> String {
+    let message = json!([
+        "",
+        {"text": TELEGRAM_TAG, "color": "aqua"},
+        {"text": format!("<{}> ", user)},
+        {"text": text}
+    ]);
+    format!("tellraw @a {}", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn parse(parser: &mut ChatParser, line: &str) -> Option<String> {
+        parser.parse(line).map(|event| event.format())
+    }
+
+    #[test]
+    fn reads_the_log_formats() {
+        let mut parser = ChatParser::new();
+        assert_eq!(
+            parse(&mut parser, "[12:00:00] [Server thread/INFO]: <Steve> hello"),
+            Some(String::from("Steve: hello"))
+        );
+        assert_eq!(
+            parse(&mut parser, "[12:00:00 INFO]: [Not Secure] <Steve> §chi> there"),
+            Some(String::from("Steve: hi> there"))
+        );
+        assert_eq!(
+            parse(&mut parser, "[12:00:00] [Server thread/INFO] [minecraft/DedicatedServer]: <Alex> hey  "),
+            Some(String::from("Alex: hey"))
+        );
+        assert_eq!(parse(&mut parser, "[12:00:00] [Server thread/WARN]: <Steve> hello"), None);
+        assert_eq!(parse(&mut parser, "[12:00:00] [Server thread/INFO]: Done (3.2s)!"), None);
+    }
+
+    #[test]
+    fn deaths_need_the_player_online() {
+        let mut parser = ChatParser::default();
+        let log = |message: &str| format!("[12:00:00] [Server thread/INFO]: {}", message);
+        assert_eq!(parse(&mut parser, &log("Steve fell from a high place")), None);
+        assert_eq!(parse(&mut parser, &log("Steve joined the game")), Some(String::from("Steve joined the game")));
+        assert_eq!(parse(&mut parser, &log("Some text joined the game")), None);
+        assert_eq!(
+            parse(&mut parser, &log("Steve fell from a high place")),
+            Some(String::from("Steve fell from a high place"))
+        );
+        assert_eq!(parse(&mut parser, &log("Steve has made the advancement [Diamonds!]")), None);
+        assert_eq!(parse(&mut parser, &log("Steve left the game")), Some(String::from("Steve left the game")));
+        assert_eq!(parse(&mut parser, &log("Steve was slain by Zombie")), None);
+    }
+
+    #[test]
+    fn tellraw_escapes_the_text() {
+        let command = tellraw_command("Bob", "\"}] hi\n@a");
+        let json: Value = serde_json::from_str(command.strip_prefix("tellraw @a ").unwrap()).unwrap();
+        assert_eq!(json[2]["text"], "<Bob> ");
+        assert_eq!(json[3]["text"], "\"}] hi\n@a");
+    }
+}
@@ -1,56 +1,205 @@
-use chrono::Local;
+use chrono::{Local, NaiveTime};
 use ini::Ini;
 use std::{
    ffi::CString,
    path::{Path, PathBuf},
 };
 
+use crate::aliases::*;
 use crate::error::*;
+use crate::permissions::Permissions;
+use crate::region::ProtectedArea;
+use crate::restart::parse_duration;
 
+const DEFAULT_SERVER_NAME: &str = "server";
+//[Server <name>] sections describe each server when there are several
+const SERVER_SECTION_PREFIX: &str = "Server ";
+//[Rules <role or user id>] sections limit the commands of the Telegram users
+const RULES_SECTION_PREFIX: &str = "Rules ";
+const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:25565";
+const DEFAULT_RESTART_WARNINGS: [u64; 4] = [600, 300, 60, 10];
+const DEFAULT_SLEEPING_MOTD: &str = "Server is sleeping. Join to wake it up";
+const DEFAULT_STARTING_MESSAGE: &str = "Server is starting. Try again in a minute";
+const DEFAULT_RESTART_WARNING_COMMAND: &str = "say Server restarting in {time}. {reason}";
+const DEFAULT_TPS_THRESHOLD: f64 = 18.0;
+const DEFAULT_LAG_ALERT_TIME: u64 = 300;
+const DEFAULT_MIN_INHABITED_TIME: u64 = 60;
+const DEFAULT_SPAWN_RADIUS: i64 = 256;
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum ControlMode {
+   Stdin,
+   Rcon,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum ServerRole {
+   Backend,
+   Proxy,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum BackupMode {
+   Offline,
+   Online,
+}
+
+#[derive(Clone)]
 pub struct Config {
+   pub name: String,
+   pub role: ServerRole,
    pub server_directory: CString,
    pub executable_name: CString,
    pub args: Vec<CString>,
+   pub control: ControlMode,
+   pub server_address: String,
+   pub query_address: Option<String>,
+
+   pub rcon_address: String,
+   pub rcon_password: String,
 
    pub backups_directory: PathBuf,
    pub backups_target: PathBuf,
    pub backups_file_format: String,
+   pub backups_mode: BackupMode,
+
+   pub restart_daily_time: Option<NaiveTime>,
+   pub restart_warnings: Vec<u64>,
+   pub restart_warning_command: String,
+
+   pub idle_timeout: Option<u64>,
+   pub idle_sleeping_motd: String,
+   pub idle_starting_message: String,
+
+   pub performance_interval: Option<u64>,
+   pub performance_tps_command: Option<String>,
+   pub performance_tps_threshold: f64,
+   pub performance_alert_time: u64,
+
+   pub versions_directory: Option<PathBuf>,
+
+   pub pruning_min_inhabited_time: u64,
+   pub pruning_spawn_radius: i64,
+   pub pruning_protected_areas: Vec<ProtectedArea>,
+
+   pub announcements_interval: Option<u64>,
+   pub announcements_times: Vec<NaiveTime>,
+   pub announcements_messages: Vec<String>,
+
+   pub aliases: Vec<Alias>,
 
    pub telegram_api_token: String,
    pub telegram_user_id: i64,
+   pub telegram_chat_id: Option<i64>,
+   pub telegram_permissions: Permissions,
 }
 
+#[derive(Clone)]
 pub struct CheckedConfig {
+   pub name: String,
+   pub role: ServerRole,
    pub server_directory: Option<CString>,
    pub executable_name: Option<CString>,
    pub args: Vec<CString>,
+   pub control: ControlMode,
+   pub server_address: Option<String>,
+   pub query_address: Option<String>,
+
+   pub rcon_address: Option<String>,
+   pub rcon_password: Option<String>,
 
    pub backups_directory: Option<PathBuf>,
    pub backups_target: Option<PathBuf>,
    pub backups_file_format: Option<String>,
+   pub backups_mode: BackupMode,
+
+   pub restart_daily_time: Option<NaiveTime>,
+   pub restart_warnings: Option<Vec<u64>>,
+   pub restart_warning_command: Option<String>,
+
+   pub idle_timeout: Option<u64>,
+   pub idle_sleeping_motd: Option<String>,
+   pub idle_starting_message: Option<String>,
+
+   pub performance_interval: Option<u64>,
+   pub performance_tps_command: Option<String>,
+   pub performance_tps_threshold: Option<f64>,
+   pub performance_alert_time: Option<u64>,
+
+   pub versions_directory: Option<PathBuf>,
+
+   pub pruning_min_inhabited_time: Option<u64>,
+   pub pruning_spawn_radius: Option<i64>,
+   pub pruning_protected_areas: Vec<ProtectedArea>,
+
+   pub announcements_interval: Option<u64>,
+   pub announcements_times: Vec<NaiveTime>,
+   pub announcements_messages: Vec<String>,
+
+   pub aliases: Vec<Alias>,
 
    pub telegram_api_token: Option<String>,
    pub telegram_user_id: Option<i64>,
+   pub telegram_chat_id: Option<i64>,
+   pub telegram_permissions: Permissions,
 }
 
 impl CheckedConfig {
    fn new_empty() -> CheckedConfig {
       CheckedConfig {
+         name: String::from(DEFAULT_SERVER_NAME),
+         role: ServerRole::Backend,
          server_directory: None,
          executable_name: None,
          args: Vec::new(),
+         control: ControlMode::Stdin,
+         server_address: None,
+         query_address: None,
+
+         rcon_address: None,
+         rcon_password: None,
 
          backups_directory: None,
          backups_target: None,
          backups_file_format: None,
+         backups_mode: BackupMode::Offline,
+
+         restart_daily_time: None,
+         restart_warnings: None,
+         restart_warning_command: None,
+
+         idle_timeout: None,
+         idle_sleeping_motd: None,
+         idle_starting_message: None,
+
+         performance_interval: None,
+         performance_tps_command: None,
+         performance_tps_threshold: None,
+         performance_alert_time: None,
+
+         versions_directory: None,
+
+         pruning_min_inhabited_time: None,
+         pruning_spawn_radius: None,
+         pruning_protected_areas: Vec::new(),
+
+         announcements_interval: None,
+         announcements_times: Vec::new(),
+         announcements_messages: Vec::new(),
+
+         aliases: Vec::new(),
 
          telegram_api_token: None,
          telegram_user_id: None,
+         telegram_chat_id: None,
+         telegram_permissions: Permissions::default(),
       }
    }
    fn check(&self) -> bool {
       self.server_directory.is_none()
-         || self.executable_name.is_none()
+         || (self.control == ControlMode::Stdin && self.executable_name.is_none())
+         || (self.control == ControlMode::Rcon
+            && (self.rcon_address.is_none() || self.rcon_password.is_none()))
          || self.backups_directory.is_none()
          || self.backups_target.is_none()
          || self.backups_file_format.is_none()
@@ -58,25 +207,139 @@ impl CheckedConfig {
          || self.telegram_user_id.is_none()
    }
 
+   //Settings that can be different for every server
+   fn set_server_key(&mut self, key: &str, val: &str) -> GenericResult<()> {
+      match key {
+         "server_directory" => self.server_directory = Some(CString::new(val).unwrap()),
+         "executable_name" => self.executable_name = Some(CString::new(val).unwrap()),
+         "arg" => self.args.push(CString::new(val).unwrap()),
+         "server_address" => self.server_address = Some(String::from(val)),
+         "query_address" => self.query_address = Some(String::from(val)),
+         "control" => {
+            self.control = match val {
+               "stdin" => ControlMode::Stdin,
+               "rcon" => ControlMode::Rcon,
+               _ => return Err(format!("Unknown control mode: {}", val).into()),
+            }
+         }
+         "role" => {
+            self.role = match val {
+               "backend" => ServerRole::Backend,
+               "proxy" => ServerRole::Proxy,
+               _ => return Err(format!("Unknown server role: {}", val).into()),
+            }
+         }
+         "rcon_address" => self.rcon_address = Some(String::from(val)),
+         "rcon_password" => self.rcon_password = Some(String::from(val)),
+         "backups_directory" => self.backups_directory = Some(PathBuf::from(val)),
+         "backups_target" => self.backups_target = Some(PathBuf::from(val)),
+         "backups_file_format" => self.backups_file_format = Some(String::from(val)),
+         "backups_mode" => {
+            self.backups_mode = match val {
+               "offline" => BackupMode::Offline,
+               "online" => BackupMode::Online,
+               _ => return Err(format!("Unknown backup mode: {}", val).into()),
+            }
+         }
+         _ => (),
+      }
+      Ok(())
+   }
+
+   fn validate(self) -> GenericResult<Config> {
+      if self.check() {
+         return Err(GenericError::Error);
+      }
+      if self.control == ControlMode::Rcon && self.idle_timeout.is_some() {
+         return Err("Idle shutdown needs the server to be spawned by the manager (control: \"stdin\")".into());
+      }
+      //typical file format
+      //Backup_%Y-%m-%d-%a
+
+      Ok(self.to_config())
+   }
+
    fn to_config(self) -> Config {
       Config {
+         name: self.name,
+         role: self.role,
          server_directory: self.server_directory.unwrap(),
-         executable_name: self.executable_name.unwrap(),
+         executable_name: self.executable_name.unwrap_or_default(),
          args: self.args,
+         control: self.control,
+         server_address: self
+            .server_address
+            .unwrap_or_else(|| String::from(DEFAULT_SERVER_ADDRESS)),
+         query_address: self.query_address,
+
+         rcon_address: self.rcon_address.unwrap_or_default(),
+         rcon_password: self.rcon_password.unwrap_or_default(),
 
          backups_directory: self.backups_directory.unwrap(),
          backups_target: self.backups_target.unwrap(),
          backups_file_format: self.backups_file_format.unwrap(),
+         backups_mode: self.backups_mode,
+
+         restart_daily_time: self.restart_daily_time,
+         restart_warnings: self
+            .restart_warnings
+            .unwrap_or_else(|| DEFAULT_RESTART_WARNINGS.to_vec()),
+         restart_warning_command: self
+            .restart_warning_command
+            .unwrap_or_else(|| String::from(DEFAULT_RESTART_WARNING_COMMAND)),
+
+         idle_timeout: self.idle_timeout,
+         idle_sleeping_motd: self
+            .idle_sleeping_motd
+            .unwrap_or_else(|| String::from(DEFAULT_SLEEPING_MOTD)),
+         idle_starting_message: self
+            .idle_starting_message
+            .unwrap_or_else(|| String::from(DEFAULT_STARTING_MESSAGE)),
+
+         performance_interval: self.performance_interval,
+         performance_tps_command: self.performance_tps_command,
+         performance_tps_threshold: self
+            .performance_tps_threshold
+            .unwrap_or(DEFAULT_TPS_THRESHOLD),
+         performance_alert_time: self
+            .performance_alert_time
+            .unwrap_or(DEFAULT_LAG_ALERT_TIME),
+
+         versions_directory: self.versions_directory,
+
+         pruning_min_inhabited_time: self
+            .pruning_min_inhabited_time
+            .unwrap_or(DEFAULT_MIN_INHABITED_TIME),
+         pruning_spawn_radius: self.pruning_spawn_radius.unwrap_or(DEFAULT_SPAWN_RADIUS),
+         pruning_protected_areas: self.pruning_protected_areas,
+
+         announcements_interval: self.announcements_interval,
+         announcements_times: self.announcements_times,
+         announcements_messages: self.announcements_messages,
+
+         aliases: self.aliases,
 
          telegram_api_token: self.telegram_api_token.unwrap(),
          telegram_user_id: self.telegram_user_id.unwrap(),
+         telegram_chat_id: self.telegram_chat_id,
+         telegram_permissions: self.telegram_permissions,
       }
    }
 }
 
 impl Config {
+   //The first server of the configuration
    pub fn new(config_p: &Path) -> GenericResult<Config> {
+      Ok(Self::load_servers(config_p)?.remove(0))
+   }
+
+   /*
+      Every [Server <name>] section is a server that takes the settings of the other
+      sections and overrides the ones it sets. Without them there is only one server
+   */
+   pub fn load_servers(config_p: &Path) -> GenericResult<Vec<Config>> {
       let mut config = CheckedConfig::new_empty();
+      let mut server_sections = Vec::new();
 
       let conf_file = Ini::load_from_file(config_p)?;
 
@@ -88,26 +351,178 @@ impl Config {
             "General" => {
                for (key, val) in prop.iter() {
                   match key {
-                     "server_directory" => {
-                        config.server_directory = Some(CString::new(val).unwrap())
+                     "name" => config.name = String::from(val),
+                     _ => config.set_server_key(key, val)?,
+                  }
+               }
+            }
+            "Rcon" => {
+               for (key, val) in prop.iter() {
+                  config.set_server_key(&format!("rcon_{}", key), val)?;
+               }
+            }
+            "Backups" => {
+               for (key, val) in prop.iter() {
+                  match key {
+                     "mode" => config.set_server_key("backups_mode", val)?,
+                     _ => config.set_server_key(key, val)?,
+                  }
+               }
+            }
+            //Telegram users allowed to send commands besides user_id, with their role
+            "Users" => {
+               for (key, val) in prop.iter() {
+                  let user_id = key
+                     .parse::<i64>()
+                     .map_err(|_| format!("Invalid Telegram user id: {}", key))?;
+                  config.telegram_permissions.set_role(user_id, val);
+               }
+            }
+            section if section.starts_with(RULES_SECTION_PREFIX) => {
+               let rules = config
+                  .telegram_permissions
+                  .rules_mut(section[RULES_SECTION_PREFIX.len()..].trim());
+               for (key, val) in prop.iter() {
+                  rules.add(key, val)?;
+               }
+            }
+            section if section.starts_with(SERVER_SECTION_PREFIX) => {
+               server_sections.push((section[SERVER_SECTION_PREFIX.len()..].trim(), prop));
+            }
+            "Restarts" => {
+               for (key, val) in prop.iter() {
+                  match key {
+                     "daily_time" => {
+                        config.restart_daily_time =
+                           Some(NaiveTime::parse_from_str(val, "%H:%M").map_err(|_| {
+                              format!("Invalid daily restart time: {}. Use the HH:MM format", val)
+                           })?)
+                     }
+                     "warnings" => {
+                        let mut warnings = Vec::new();
+                        for w in val.split_whitespace() {
+                           warnings.push(
+                              parse_duration(w)
+                                 .ok_or_else(|| format!("Invalid restart warning time: {}", w))?,
+                           );
+                        }
+                        config.restart_warnings = Some(warnings);
+                     }
+                     "warning_command" => {
+                        config.restart_warning_command = Some(String::from(val))
                      }
-                     "executable_name" => config.executable_name = Some(CString::new(val).unwrap()),
-                     "arg" => config.args.push(CString::new(val).unwrap()),
                      _ => (),
                   }
                }
             }
-            "Backups" => {
+            "Idle" => {
+               for (key, val) in prop.iter() {
+                  match key {
+                     "timeout" => {
+                        config.idle_timeout = Some(
+                           parse_duration(val)
+                              .ok_or_else(|| format!("Invalid idle timeout: {}", val))?,
+                        )
+                     }
+                     "sleeping_motd" => config.idle_sleeping_motd = Some(String::from(val)),
+                     "starting_message" => config.idle_starting_message = Some(String::from(val)),
+                     _ => (),
+                  }
+               }
+            }
+            "Performance" => {
                for (key, val) in prop.iter() {
                   match key {
-                     "backups_directory" => config.backups_directory = Some(PathBuf::from(val)),
-                     "backups_target" => config.backups_target = Some(PathBuf::from(val)),
-                     "backups_file_format" => config.backups_file_format = Some(String::from(val)),
+                     "interval" => {
+                        config.performance_interval = Some(
+                           parse_duration(val)
+                              .ok_or_else(|| format!("Invalid performance interval: {}", val))?,
+                        )
+                     }
+                     "tps_command" => config.performance_tps_command = Some(String::from(val)),
+                     "tps_threshold" => {
+                        config.performance_tps_threshold = Some(
+                           val.parse::<f64>()
+                              .map_err(|_| format!("Invalid TPS threshold: {}", val))?,
+                        )
+                     }
+                     "alert_time" => {
+                        config.performance_alert_time = Some(
+                           parse_duration(val)
+                              .ok_or_else(|| format!("Invalid lag alert time: {}", val))?,
+                        )
+                     }
+                     _ => (),
+                  }
+               }
+            }
+            "Versions" => {
+               for (key, val) in prop.iter() {
+                  if key == "directory" {
+                     config.versions_directory = Some(PathBuf::from(val));
+                  }
+               }
+            }
+            "Pruning" => {
+               for (key, val) in prop.iter() {
+                  match key {
+                     "min_inhabited_time" => {
+                        config.pruning_min_inhabited_time = Some(
+                           parse_duration(val)
+                              .ok_or_else(|| format!("Invalid inhabited time: {}", val))?,
+                        )
+                     }
+                     "spawn_radius" => {
+                        config.pruning_spawn_radius = Some(
+                           val.parse::<i64>()
+                              .map_err(|_| format!("Invalid spawn radius: {}", val))?,
+                        )
+                     }
+                     "protected_area" => config.pruning_protected_areas.push(
+                        ProtectedArea::parse(val)
+                           .ok_or_else(|| format!("Invalid protected area: {}", val))?,
+                     ),
+                     _ => (),
+                  }
+               }
+            }
+
+            "Announcements" => {
+               for (key, val) in prop.iter() {
+                  match key {
+                     "interval" => {
+                        config.announcements_interval = Some(
+                           parse_duration(val)
+                              .filter(|i| *i > 0)
+                              .ok_or_else(|| format!("Invalid announcement interval: {}", val))?,
+                        )
+                     }
+                     "time" => config.announcements_times.push(
+                        NaiveTime::parse_from_str(val, "%H:%M")
+                           .map_err(|_| format!("Invalid announcement time: {}. Use the HH:MM format", val))?,
+                     ),
+                     "message" => config.announcements_messages.push(String::from(val)),
                      _ => (),
                   }
                }
             }
 
+            //Every line of an alias is one of its commands
+            "Aliases" => {
+               for (key, val) in prop.iter() {
+                  let index = match config.aliases.iter().position(|a| a.name() == key) {
+                     Some(i) => i,
+                     None => {
+                        config.aliases.push(Alias::new(key));
+                        config.aliases.len() - 1
+                     }
+                  };
+                  config.aliases[index]
+                     .push(val)
+                     .ok_or_else(|| format!("Invalid command of the alias {}: {}", key, val))?;
+               }
+            }
+
             "Telegram" => {
                for (key, val) in prop.iter() {
                   match key {
@@ -115,6 +530,12 @@ impl Config {
                      "user_id" => {
                         config.telegram_user_id = Some(String::from(val).parse::<i64>().unwrap())
                      }
+                     "chat_id" => {
+                        config.telegram_chat_id = Some(
+                           val.parse::<i64>()
+                              .map_err(|_| format!("Invalid chat id: {}", val))?,
+                        )
+                     }
                      _ => (),
                   }
                }
@@ -123,13 +544,37 @@ impl Config {
          }
       }
 
-      if config.check() {
-         return Err(GenericError::Error);
+      check_aliases(&config.aliases)?;
+
+      if server_sections.is_empty() {
+         return Ok(vec![config.validate()?]);
       }
-      //typical file format
-      //Backup_%Y-%m-%d-%a
+      let mut servers: Vec<Config> = Vec::new();
+      for (name, prop) in server_sections {
+         if name.is_empty() || name.contains(char::is_whitespace) || servers.iter().any(|s| s.name == name) {
+            return Err(format!("Invalid or repeated server name: {}", name).into());
+         }
+         let mut server = config.clone();
+         server.name = String::from(name);
+         //The args of the server replace the general ones
+         if prop.iter().any(|(key, _)| key == "arg") {
+            server.args.clear();
+         }
+         for (key, val) in prop.iter() {
+            server.set_server_key(key, val)?;
+         }
+         servers.push(server.validate()?);
+      }
+      Ok(servers)
+   }
 
-      Ok(config.to_config())
+   pub fn server_path(&self) -> PathBuf {
+      PathBuf::from(
+         self
+            .server_directory
+            .to_str()
+            .expect("Encoding error in the server directory"),
+      )
    }
 
    pub fn generate_backup_name(&self, i: u8) -> PathBuf {
@@ -0,0 +1,153 @@
+use crate::io::*;
+use crate::*;
+
+use lazy_static::*;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+pub const CRASH_REPORTS_DIRECTORY: &str = "crash-reports";
+
+const STACK_TRACE_LINES: usize = 5;
+//Reports modified more recently could still be being written
+const SETTLE_TIME: u64 = 1;
+
+lazy_static! {
+    //Reports already sent or that were there when the manager started
+    static ref KNOWN_REPORTS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+pub struct CrashReport {
+    pub path: PathBuf,
+    pub description: Option<String>,
+    pub stack_trace: Vec<String>,
+    pub suspects: Vec<String>,
+}
+
+impl CrashReport {
+    pub fn load(path: &Path) -> std::io::Result<CrashReport> {
+        let content = fs::read_to_string(path)?;
+        let lines: Vec<&str> = content.lines().collect();
+
+        let description_index = lines.iter().position(|l| l.starts_with("Description:"));
+        let description = description_index
+            .map(|i| lines[i]["Description:".len()..].trim().to_string());
+
+        //The exception comes right after the description, followed by its frames
+        let stack_trace = match description_index {
+            Some(i) => lines[i + 1..]
+                .iter()
+                .skip_while(|l| l.trim().is_empty())
+                .take_while(|l| !l.trim().is_empty())
+                .take(STACK_TRACE_LINES + 1)
+                .map(|l| l.trim().to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        /*
+            Forge and Fabric write the suspects in the same line
+            or indented in the following ones
+        */
+        let mut suspects = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let line = line.trim();
+            if !line.starts_with("Suspected Mods:") && !line.starts_with("Suspected Plugins:") {
+                continue;
+            }
+            let value = line.split_once(':').map(|(_, v)| v).unwrap_or("").trim();
+            if !value.is_empty() && !value.eq_ignore_ascii_case("NONE") {
+                suspects.extend(value.split(',').map(|s| s.trim().to_string()));
+            }
+            suspects.extend(
+                lines[i + 1..]
+                    .iter()
+                    .take_while(|l| l.starts_with('\t') || l.starts_with("  "))
+                    .map(|l| l.trim().to_string())
+                    .filter(|l| !l.is_empty()),
+            );
+        }
+        suspects.dedup();
+
+        Ok(CrashReport {
+            path: PathBuf::from(path),
+            description,
+            stack_trace,
+            suspects,
+        })
+    }
+
+    pub fn summary(&self) -> String {
+        let file = self
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut summary = vec![format!(
+            "The server crashed ({}): {}",
+            file,
+            self.description.as_deref().unwrap_or("no description")
+        )];
+        summary.extend(self.stack_trace.iter().map(|l| format!("    {}", l)));
+        if !self.suspects.is_empty() {
+            summary.push(format!("Suspects: {}", self.suspects.join(", ")));
+        }
+        summary.join("\n")
+    }
+}
+
+pub fn list_reports(server_path: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(server_path.join(CRASH_REPORTS_DIRECTORY)) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().map(|e| e == "txt").unwrap_or(false))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn ignore_existing_reports(server_path: &Path) {
+    KNOWN_REPORTS
+        .lock()
+        .unwrap()
+        .extend(list_reports(server_path));
+}
+
+/*
+    Sends the summary and the file of every report not sent yet. Both the
+    watcher and the thread of a server that died look for them, so the
+    report is sent before the manager decides whether to exit. Once the
+    server is dead its reports are complete and don't need to settle
+*/
+pub fn send_new_reports(server_path: &Path, out: &OutputSender, server_dead: bool) {
+    let mut known = KNOWN_REPORTS.lock().unwrap();
+    for path in list_reports(server_path) {
+        let settled = server_dead
+            || fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .map(|t| t.elapsed().unwrap_or_default() >= Duration::from_secs(SETTLE_TIME))
+                .unwrap_or(false);
+        if known.contains(&path) || !settled {
+            continue;
+        }
+        match CrashReport::load(&path) {
+            Ok(report) => {
+                errorln!(out, "{}", report.summary());
+                out.send(OutputPacket::File {
+                    caption: String::from("Full crash report"),
+                    path: path.clone(),
+                })
+                .unwrap();
+            }
+            Err(e) => {
+                errorln!(out, "Could not read crash report {}: {}", path.display(), e);
+            }
+        }
+        known.insert(path);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::io::*;
+use crate::ping::chat_to_text;
+use crate::properties::world_path;
+use crate::server_handler::ServerHandler;
+use crate::versions::jar_version_info;
+use crate::*;
+
+use serde_json::Value;
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+use zip::ZipArchive;
+
+const DATAPACKS_DIRECTORY: &str = "datapacks";
+const DISABLED_DIRECTORY: &str = "disabled-datapacks";
+const PACK_METADATA: &str = "pack.mcmeta";
+
+pub struct Datapack {
+    pub path: PathBuf,
+    pub description: String,
+    pub pack_format: Option<i64>,
+    //Range of formats declared by the pack, when it declares one
+    pub supported_formats: Option<(i64, i64)>,
+}
+
+impl Datapack {
+    pub fn load(path: &Path) -> GenericResult<Datapack> {
+        let content = if path.is_dir() {
+            fs::read_to_string(path.join(PACK_METADATA))?
+        } else {
+            read_zip_entry(path, PACK_METADATA)?
+        };
+        let metadata: Value = serde_json::from_str(&content)
+            .map_err(|e| GenericError::from(format!("Invalid {}: {}", PACK_METADATA, e)))?;
+        let pack = &metadata["pack"];
+        Ok(Datapack {
+            path: PathBuf::from(path),
+            description: chat_to_text(&pack["description"]),
+            pack_format: pack["pack_format"]
+                .as_i64()
+                .or_else(|| pack["min_format"].as_i64()),
+            supported_formats: format_range(&pack["supported_formats"])
+                .or_else(|| Some((pack["min_format"].as_i64()?, pack["max_format"].as_i64()?))),
+        })
+    }
+
+    //The name used by the datapack command
+    pub fn name(&self) -> String {
+        format!(
+            "file/{}",
+            self.path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        )
+    }
+
+    fn supports(&self, format: i64) -> bool {
+        match (self.supported_formats, self.pack_format) {
+            (Some((min, max)), _) => min <= format && format <= max,
+            (None, Some(pack_format)) => pack_format == format,
+            (None, None) => false,
+        }
+    }
+}
+
+//supported_formats can be a number, [min, max] or {min_inclusive, max_inclusive}
+fn format_range(value: &Value) -> Option<(i64, i64)> {
+    match value {
+        Value::Number(n) => n.as_i64().map(|n| (n, n)),
+        Value::Array(range) => Some((range.first()?.as_i64()?, range.get(1)?.as_i64()?)),
+        Value::Object(range) => Some((
+            range.get("min_inclusive")?.as_i64()?,
+            range.get("max_inclusive")?.as_i64()?,
+        )),
+        _ => None,
+    }
+}
+
+fn read_zip_entry(path: &Path, name: &str) -> GenericResult<String> {
+    let mut archive =
+        ZipArchive::new(File::open(path)?).map_err(|e| GenericError::from(format!("{}", e)))?;
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|_| GenericError::from(format!("There is no {} in {}", name, path.display())))?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+//Data pack format of the server jar
+pub fn server_pack_format(config: &Config) -> Option<i64> {
+    let version = jar_version_info(config)?;
+    let pack_version = &version["pack_version"];
+    pack_version
+        .as_i64()
+        .or_else(|| pack_version["data"].as_i64())
+        .or_else(|| pack_version["data_major"].as_i64())
+}
+
+fn list_datapacks(directory: &Path) -> Vec<Datapack> {
+    let out = get_output_sender();
+    let mut paths: Vec<PathBuf> = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_dir() || p.extension().map(|e| e == "zip").unwrap_or(false))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+        .iter()
+        .filter_map(|p| match Datapack::load(p) {
+            Ok(pack) => Some(pack),
+            Err(e) => {
+                warnln!(out, "{} is not a datapack: {}", p.display(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn find_datapack(directory: &Path, name: &str) -> Option<Datapack> {
+    let name = name.trim_start_matches("file/");
+    list_datapacks(directory).into_iter().find(|p| {
+        let file = p.name();
+        let file = file.trim_start_matches("file/");
+        file.eq_ignore_ascii_case(name) || file.trim_end_matches(".zip").eq_ignore_ascii_case(name)
+    })
+}
+
+/*
+    datapacks list: shows the datapacks of the world
+    datapacks enable|disable <name>: uses the datapack command if the server
+    is running and moves the datapack in or out of the world otherwise.
+    The user needs to be allowed to use the datapack command either way
+    datapacks install <zip>: copies a zip from the server directory
+*/
+pub fn datapacks_command(
+    args: &[&str],
+    config: &Config,
+    server: Option<&mut ServerHandler>,
+    user: Option<i64>,
+) {
+    let out = get_output_sender();
+    if let [action @ ("enable" | "disable"), name] = args {
+        let command = format!("datapack {} \"{}\"", action, prefixed(name));
+        let by = format!("datapacks {} {}", action, name);
+        if !config.telegram_permissions.allows(user, &command, &by) {
+            return;
+        }
+    }
+    let world = world_path(config);
+    let directory = world.join(DATAPACKS_DIRECTORY);
+    let disabled = world.join(DISABLED_DIRECTORY);
+
+    let result = match (args, server) {
+        ([], _) | (["list"], _) => {
+            list(config, &directory, &disabled);
+            Ok(())
+        }
+        //Datapacks moved out of the world have to be found by the server again
+        (["enable", name], Some(_)) if find_datapack(&disabled, name).is_some() => {
+            move_datapack(&disabled, &directory, name).map(|name| {
+                infoln!(
+                    out,
+                    "{} moved back to the world. Use the reload command to load it",
+                    name
+                );
+            })
+        }
+        (["enable", name], Some(server)) => {
+            server.sendln(format!("datapack enable \"{}\"", prefixed(name)).as_bytes())
+        }
+        (["disable", name], Some(server)) => {
+            server.sendln(format!("datapack disable \"{}\"", prefixed(name)).as_bytes())
+        }
+        (["enable", name], None) => move_datapack(&disabled, &directory, name).map(|name| {
+            infoln!(out, "{} enabled", name);
+        }),
+        (["disable", name], None) => move_datapack(&directory, &disabled, name).map(|name| {
+            infoln!(out, "{} disabled", name);
+        }),
+        (["install", path], server) => install(config, &directory, path).map(|pack| {
+            infoln!(out, "{} installed: {}", pack.name(), pack.description);
+            if let Some(format) = server_pack_format(config) {
+                if !pack.supports(format) {
+                    warnln!(
+                        out,
+                        "{} was made for another version of the game",
+                        pack.name()
+                    );
+                }
+            }
+            if server.is_some() {
+                infoln!(out, "Use the reload command to load it");
+            }
+        }),
+        _ => {
+            warnln!(
+                out,
+                "Usage: datapacks [list | enable <name> | disable <name> | install <zip>]"
+            );
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        errorln!(out, "{}", e);
+    }
+}
+
+fn prefixed(name: &str) -> String {
+    if name.starts_with("file/") {
+        String::from(name)
+    } else {
+        format!("file/{}", name)
+    }
+}
+
+fn list(config: &Config, directory: &Path, disabled: &Path) {
+    let out = get_output_sender();
+    let format = server_pack_format(config);
+    let packs = list_datapacks(directory);
+    infoln!(
+        out,
+        "Datapacks ({}){}:",
+        packs.len(),
+        format
+            .map(|f| format!(". Server pack format: {}", f))
+            .unwrap_or_default()
+    );
+    for pack in &packs {
+        let incompatible = match format {
+            Some(f) if !pack.supports(f) => " [INCOMPATIBLE]",
+            _ => "",
+        };
+        infoln!(
+            out,
+            "{} (format {}){}: {}",
+            pack.name(),
+            pack.pack_format
+                .map(|f| f.to_string())
+                .unwrap_or_else(|| String::from("?")),
+            incompatible,
+            pack.description
+        );
+    }
+    let disabled = list_datapacks(disabled);
+    if !disabled.is_empty() {
+        let names: Vec<String> = disabled.iter().map(|p| p.name()).collect();
+        infoln!(out, "Moved out of the world: {}", names.join(", "));
+    }
+}
+
+fn move_datapack(from: &Path, to: &Path, name: &str) -> GenericResult<String> {
+    let pack = find_datapack(from, name)
+        .ok_or_else(|| GenericError::from(format!("There is no {} in {}", name, from.display())))?;
+    let file = pack.path.file_name().ok_or("Invalid datapack path")?;
+    fs::create_dir_all(to)?;
+    fs::rename(&pack.path, to.join(file))?;
+    Ok(pack.name())
+}
+
+//Only zips inside the server directory can be installed
+fn install(config: &Config, directory: &Path, path: &str) -> GenericResult<Datapack> {
+    let server_path = config.server_path().canonicalize()?;
+    let source = server_path.join(path).canonicalize()?;
+    if !source.starts_with(&server_path) {
+        return Err("The datapack must be inside the server directory".into());
+    }
+    if source.extension().map(|e| e != "zip").unwrap_or(true) {
+        return Err("Datapacks are installed from zip files".into());
+    }
+    //Fails if it isn't a datapack
+    Datapack::load(&source)?;
+
+    let file = source.file_name().ok_or("Invalid datapack path")?;
+    let destination = directory.join(file);
+    if destination.exists() {
+        return Err(format!("{} is already installed", destination.display()).into());
+    }
+    fs::create_dir_all(directory)?;
+    fs::copy(&source, &destination)?;
+    Datapack::load(&destination)
+}
@@ -0,0 +1,39 @@
+use crate::io::*;
+use crate::*;
+
+use chrono::Local;
+use std::fs;
+
+pub const EULA_FILE: &str = "eula.txt";
+pub const EULA_URL: &str = "https://aka.ms/MinecraftEULA";
+//Written by the server right before exiting when the EULA has not been accepted
+pub const EULA_LOG_LINE: &str = "You need to agree to the EULA in order to run the server";
+
+pub fn eula_accepted(config: &Config) -> bool {
+    match fs::read_to_string(config.server_path().join(EULA_FILE)) {
+        Ok(content) => content
+            .lines()
+            .map(|l| l.trim())
+            .any(|l| l.eq_ignore_ascii_case("eula=true")),
+        Err(_) => false,
+    }
+}
+
+pub fn accept_eula(config: &Config) -> GenericResult<()> {
+    let content = format!(
+        "#By changing the setting below to TRUE you are indicating your agreement to our EULA ({}).\n#{}\neula=true\n",
+        EULA_URL,
+        Local::now().format("%a %b %d %H:%M:%S %Z %Y")
+    );
+    fs::write(config.server_path().join(EULA_FILE), content)?;
+    Ok(())
+}
+
+pub fn notify_eula() {
+    let out = get_output_sender();
+    errorln!(
+        out,
+        "The Minecraft EULA has not been accepted, so the server can't start. Read it at {} and send \"eula accept\" to accept it",
+        EULA_URL
+    );
+}
@@ -0,0 +1,184 @@
+use crate::io::*;
+use crate::ping::*;
+use crate::*;
+
+use serde_json::json;
+use std::{
+    io::ErrorKind,
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+pub const SLEEP_COMMAND: &str = "sleep";
+pub const START_COMMAND: &str = "start";
+
+const IDLE_POLLING_TIME: u64 = 60;
+const ACCEPT_POLLING_TIME: u64 = 200;
+const CLIENT_TIMEOUT: u64 = 5;
+const STATUS_STATE: i32 = 1;
+const LOGIN_STATE: i32 = 2;
+
+/********** Idle monitor **********/
+
+//Sends the sleep command once the server has been empty for the configured time
+pub struct IdleMonitor {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl IdleMonitor {
+    pub fn start(config: &Config, timeout: u64) -> IdleMonitor {
+        let address = config.server_address.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_c = stop.clone();
+        let input = get_input_sender();
+        let handle = thread::spawn(move || {
+            let mut last_activity = Instant::now();
+            loop {
+                thread::park_timeout(Duration::from_secs(IDLE_POLLING_TIME));
+                if stop_c.load(Ordering::SeqCst) {
+                    break;
+                }
+                match ping(&address) {
+                    Ok(status) if status.online_players == 0 => (),
+                    //The server could be starting or restarting, that doesn't count as idle
+                    _ => last_activity = Instant::now(),
+                }
+                if last_activity.elapsed() >= Duration::from_secs(timeout) {
+                    input
+                        .send(InputPacket::Command(String::from(SLEEP_COMMAND) + "\n", None))
+                        .unwrap();
+                    break;
+                }
+            }
+        });
+        IdleMonitor { stop, handle }
+    }
+
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.thread().unpark();
+        self.handle.join().unwrap();
+    }
+}
+
+/********** Wake listener **********/
+
+/*
+    While the server sleeps the manager takes its port and answers
+    the server list pings itself. When someone tries to join, the port
+    is released and the start command is sent to the main loop
+*/
+pub struct WakeListener {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl WakeListener {
+    pub fn start(config: &Config) -> GenericResult<WakeListener> {
+        let (_, port) = split_address(&config.server_address)?;
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+
+        let motd = config.idle_sleeping_motd.clone();
+        let starting_message = config.idle_starting_message.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_c = stop.clone();
+
+        let out = get_output_sender();
+        let input = get_input_sender();
+        let handle = thread::spawn(move || {
+            while !stop_c.load(Ordering::SeqCst) {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(ACCEPT_POLLING_TIME));
+                        continue;
+                    }
+                    Err(e) => {
+                        errorln!(out, "Error accepting a connection: {}", e);
+                        continue;
+                    }
+                };
+                match answer_client(stream, &motd, &starting_message) {
+                    Ok(Some(player)) => {
+                        infoln!(out, "{} wants to play. Waking up the server", player);
+                        input
+                            .send(InputPacket::Command(String::from(START_COMMAND) + "\n", None))
+                            .unwrap();
+                        break;
+                    }
+                    Ok(None) => (),
+                    Err(e) => {
+                        debugln!(out, "Error answering a client: {}", e);
+                    }
+                }
+            }
+        });
+        Ok(WakeListener { stop, handle })
+    }
+
+    //The port is free once this returns
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.join().unwrap();
+    }
+}
+
+//Returns the name of the player if the client tried to join
+fn answer_client(
+    mut stream: TcpStream,
+    motd: &str,
+    starting_message: &str,
+) -> GenericResult<Option<String>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(CLIENT_TIMEOUT)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(CLIENT_TIMEOUT)))?;
+
+    let (id, data) = read_packet(&mut stream)?;
+    if id != 0x00 {
+        return Err(format!("Unexpected handshake packet {}", id).into());
+    }
+    let mut data = data.as_slice();
+    let protocol = read_varint(&mut data)?;
+    read_string(&mut data)?;
+    if data.len() < 2 {
+        return Err("Truncated handshake".into());
+    }
+    //Skipping the port
+    let next_state = read_varint(&mut &data[2..])?;
+
+    match next_state {
+        STATUS_STATE => {
+            let status = json!({
+                "version": { "name": "Sleeping", "protocol": protocol },
+                "players": { "max": 0, "online": 0 },
+                "description": { "text": motd },
+            });
+            let mut response = Vec::new();
+            write_string(&mut response, &status.to_string());
+
+            //Status request, then ping. Clients may close the connection without pinging
+            read_packet(&mut stream)?;
+            write_packet(&mut stream, 0x00, &response)?;
+            if let Ok((0x01, payload)) = read_packet(&mut stream) {
+                write_packet(&mut stream, 0x01, &payload)?;
+            }
+            Ok(None)
+        }
+        LOGIN_STATE => {
+            let (_, login_start) = read_packet(&mut stream)?;
+            let player = read_string(&mut login_start.as_slice())?;
+            let mut disconnect = Vec::new();
+            write_string(&mut disconnect, &json!({ "text": starting_message }).to_string());
+            write_packet(&mut stream, 0x00, &disconnect)?;
+            Ok(Some(player))
+        }
+        _ => Err(format!("Unknown next state {}", next_state).into()),
+    }
+}
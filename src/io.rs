@@ -1,18 +1,98 @@
 use lazy_static::*;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, SendError, Sender};
 use std::sync::Mutex;
 
 type OutputPacketType = OutputPacket;
-type InputPacketType = String;
+//Packets are tagged with the server they come from, if any
+type InputPacketType = (Option<String>, InputPacket);
 
 lazy_static! {
     static ref OUT: Mutex<ChannelProvider<OutputPacketType>> = Mutex::new(ChannelProvider::new());
     static ref IN: Mutex<ChannelProvider<InputPacketType>> = Mutex::new(ChannelProvider::new());
 }
 
-pub fn get_input_sender() -> Sender<InputPacketType> {
+static PREFIX_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/*
+    Each server is managed from its own thread. The senders created in
+    that thread are tagged with the name of the server, so threads spawned
+    for a server must get their senders before being spawned
+*/
+thread_local! {
+    static SERVER: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn set_thread_server(name: Option<&str>) {
+    SERVER.with(|s| *s.borrow_mut() = name.map(String::from));
+}
+
+pub fn thread_server() -> Option<String> {
+    SERVER.with(|s| s.borrow().clone())
+}
+
+//Runs f as if it was running in the thread of the server
+pub fn with_server<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    let previous = thread_server();
+    set_thread_server(Some(name));
+    let result = f();
+    set_thread_server(previous.as_deref());
+    result
+}
+
+//Output is only prefixed with the name of the server when there are several
+pub fn prefix_output(prefix: bool) {
+    PREFIX_OUTPUT.store(prefix, Ordering::SeqCst);
+}
+
+#[derive(Clone)]
+pub struct InputSender {
+    sender: Sender<InputPacketType>,
+    server: Option<String>,
+}
+
+impl InputSender {
+    pub fn send(&self, packet: InputPacket) -> Result<(), SendError<InputPacket>> {
+        self.sender
+            .send((self.server.clone(), packet))
+            .map_err(|SendError((_, packet))| SendError(packet))
+    }
+}
+
+#[derive(Clone)]
+pub struct OutputSender {
+    sender: Sender<OutputPacketType>,
+    prefix: Option<String>,
+}
+
+impl OutputSender {
+    pub fn send(&self, packet: OutputPacket) -> Result<(), SendError<OutputPacket>> {
+        let packet = match (&self.prefix, packet) {
+            (Some(p), OutputPacket::Message { level, message }) => OutputPacket::Message {
+                level,
+                message: format!("[{}] {}", p, message),
+            },
+            (Some(p), OutputPacket::File { caption, path }) => OutputPacket::File {
+                caption: format!("[{}] {}", p, caption),
+                path,
+            },
+            (Some(p), OutputPacket::Chat(message)) => {
+                OutputPacket::Chat(format!("[{}] {}", p, message))
+            }
+            (_, packet) => packet,
+        };
+        self.sender.send(packet)
+    }
+}
+
+pub fn get_input_sender() -> InputSender {
     let tmp = IN.lock().unwrap();
-    tmp.get_sender()
+    InputSender {
+        sender: tmp.get_sender(),
+        server: thread_server(),
+    }
 }
 
 pub fn get_input_receiver() -> Receiver<InputPacketType> {
@@ -20,9 +100,12 @@ pub fn get_input_receiver() -> Receiver<InputPacketType> {
     tmp.get_receiver()
 }
 
-pub fn get_output_sender() -> Sender<OutputPacketType> {
+pub fn get_output_sender() -> OutputSender {
     let tmp = OUT.lock().unwrap();
-    tmp.get_sender()
+    OutputSender {
+        sender: tmp.get_sender(),
+        prefix: thread_server().filter(|_| PREFIX_OUTPUT.load(Ordering::SeqCst)),
+    }
 }
 
 pub fn get_output_receiver() -> Receiver<OutputPacketType> {
@@ -53,6 +136,23 @@ impl<T> ChannelProvider<T> {
     }
 }
 
+//Commands come from the users, events from the manager itself
+#[derive(Clone)]
+pub enum InputPacket {
+    //Telegram user that sent it, None for the console and the manager
+    Command(String, Option<i64>),
+    ServerDied,
+    Lag { ms: u64, ticks: u64 },
+    PerformanceSample,
+    //Answer to the TPS command, None if the server didn't give one
+    TpsAnswer(Option<String>),
+    Announce,
+    //Sent by the thread of a server when it stops managing it
+    ServerExited,
+    //Message of the chat bridge, shown in the game
+    Chat { user: String, text: String },
+}
+
 //Non memory optimum structure (two level labeled struct), but more readable
 
 pub enum OutputPacket {
@@ -60,6 +160,15 @@ pub enum OutputPacket {
         level: OutputMessageType,
         message: String,
     },
+    //Sinks that can't take files only get the caption
+    File {
+        caption: String,
+        path: PathBuf,
+    },
+    //Chat of the game, only sent to the chat bridge
+    Chat(String),
+    //Answer for a single Telegram user
+    Reply { user: i64, message: String },
     Terminate,
 }
 
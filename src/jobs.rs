@@ -14,21 +14,36 @@ trait JobCleaner {
 
 pub struct JobManager {
     sync_jobs: Vec<Box<dyn JobCleaner>>,
+    //Stopped the last, so the messages of the other jobs are delivered
+    output_job: OutputManagerJob,
 
     telegram_cleaner: TelegramManagerCleaner,
     async_thread_handler: JoinHandle<()>,
 }
 
 impl JobManager {
-    pub fn start_jobs(config: &Config) -> JobManager {
+    //The Telegram settings are the same for every server
+    pub fn start_jobs(servers: &[Config]) -> JobManager {
+        let config = &servers[0];
         //Sync jobs
-        let mut jobs: Vec<Box<dyn JobCleaner>> = Vec::with_capacity(1);
-        jobs.push(Box::new(OutputManagerJob::start(config)));
-        jobs.push(Box::new(StdinManagerJob::start()));
+        let output_job = OutputManagerJob::start(config);
+        let mut jobs: Vec<Box<dyn JobCleaner>> = vec![Box::new(StdinManagerJob::start())];
+        for server in servers {
+            with_server(&server.name, || {
+                if let Some(query_address) = &server.query_address {
+                    jobs.push(Box::new(PlayerTrackerJob::start(query_address)));
+                }
+                jobs.push(Box::new(CrashWatcherJob::start(server)));
+            });
+        }
 
         //Async Jobs
-        let (telegram_cleaner, telegram_routine) =
-            telegram_job(&config.telegram_api_token, config.telegram_user_id);
+        let (telegram_cleaner, telegram_routine) = telegram_job(
+            &config.telegram_api_token,
+            config.telegram_user_id,
+            config.telegram_chat_id,
+            config.telegram_permissions.clone(),
+        );
 
         //let (discord_cleaner, discord_routine) =
         //    discord_job(&config.telegram_api_token, config.telegram_user_id);
@@ -49,6 +64,7 @@ impl JobManager {
         let sync_jobs = jobs;
         JobManager {
             sync_jobs,
+            output_job,
             telegram_cleaner,
             async_thread_handler,
         }
@@ -60,33 +76,64 @@ impl JobManager {
         }
         self.telegram_cleaner.terminate();
         self.async_thread_handler.join().unwrap();
+        Box::new(self.output_job).terminate();
     }
 }
 
 /********* Telegram receiving job ********/
 
+use crate::permissions::Permissions;
 use futures::Future;
+use tbot::contexts::methods::ChatMethods;
 use tokio::sync::oneshot;
 
-fn telegram_job(api_token: &str, authorized_user_id: i64) -> (TelegramManagerCleaner, impl Future) {
+/*
+    Messages of the bridged chat go to the game and are never run as commands.
+    Commands of the users in the Users section are checked against their rules
+    before they reach the server, and they are told when they are denied
+*/
+fn telegram_job(
+    api_token: &str,
+    authorized_user_id: i64,
+    chat_id: Option<i64>,
+    permissions: Permissions,
+) -> (TelegramManagerCleaner, impl Future) {
+    let permissions = Arc::new(permissions);
     let mut bot = tbot::Bot::new(String::from(api_token)).event_loop();
-    bot.text(move |context| async move {
-        let out = get_output_sender();
-        let input = get_input_sender();
-        let data = &context.text.value;
-        let user = &context.from.as_ref().unwrap().first_name;
-        let user_id = context.from.as_ref().unwrap().id.0;
-        infoln!(out, "Telegram message from {}: {}", user, data);
-        if user_id == authorized_user_id {
-            let mut data = String::from(data);
-            data.push_str("\n");
-            input.send(data).unwrap();
-        } else {
-            warnln!(
-                out,
-                "Telegram user does not have permission to send commands"
-            );
-            warnln!(out, "User: {}", user_id);
+    bot.text(move |context| {
+        let permissions = permissions.clone();
+        async move {
+            let out = get_output_sender();
+            let input = get_input_sender();
+            let data = &context.text.value;
+            let user = &context.from.as_ref().unwrap().first_name;
+            let user_id = context.from.as_ref().unwrap().id.0;
+            if Some(context.chat.id.0) == chat_id {
+                input
+                    .send(InputPacket::Chat {
+                        user: user.clone(),
+                        text: data.clone(),
+                    })
+                    .unwrap();
+                return;
+            }
+            infoln!(out, "Telegram message from {}: {}", user, data);
+            if user_id != authorized_user_id && !permissions.has_user(user_id) {
+                warnln!(
+                    out,
+                    "Telegram user does not have permission to send commands"
+                );
+                warnln!(out, "User: {}", user_id);
+            } else if let Err(e) = permissions.check(user_id, data) {
+                warnln!(out, "Command of {} ({}) denied: {}", user, user_id, e);
+                if let Err(e) = context.send_message_in_reply(&e.to_string()).call().await {
+                    warnln!(out, "Could not answer {}: {}", user, e);
+                }
+            } else {
+                let mut data = String::from(data);
+                data.push_str("\n");
+                input.send(InputPacket::Command(data, Some(user_id))).unwrap();
+            }
         }
     });
 
@@ -109,8 +156,9 @@ struct TelegramManagerCleaner {
 }
 
 impl TelegramManagerCleaner {
+    //The bot could have stopped already if it lost the connection
     fn terminate(self) {
-        self.tx_end.send(()).unwrap();
+        let _ = self.tx_end.send(());
     }
 }
 
@@ -176,6 +224,8 @@ impl OutputManagerJob {
         let recv = get_output_receiver();
         let mut tel_out =
             TelegramMessageSender::new(&config.telegram_api_token, config.telegram_user_id);
+        let chat_id = config.telegram_chat_id;
+        let user_id = config.telegram_user_id;
 
         let handle = std::thread::spawn(move || 'main: loop {
             let s = match recv.recv() {
@@ -201,6 +251,30 @@ impl OutputManagerJob {
                         println!("[WARN] Could not send log to telegram:{}", e);
                     }
                 }
+
+                OutputPacket::File { caption, path } => {
+                    println!("[FILE] {} ({})", caption, path.display());
+                    if let Err(e) = tel_out.send_file(&path, &caption) {
+                        println!("[WARN] Could not send file to telegram:{}", e);
+                    }
+                }
+
+                OutputPacket::Chat(message) => {
+                    if let Some(chat_id) = chat_id {
+                        if let Err(e) = tel_out.send_message_to(chat_id, &message) {
+                            println!("[WARN] Could not send chat to telegram:{}", e);
+                        }
+                    }
+                }
+
+                //The user in user_id already gets every message
+                OutputPacket::Reply { user, message } => {
+                    if user != user_id {
+                        if let Err(e) = tel_out.send_message_to(user, &message) {
+                            println!("[WARN] Could not send reply to telegram:{}", e);
+                        }
+                    }
+                }
             }
         });
         OutputManagerJob { handle }
@@ -247,7 +321,7 @@ impl StdinManagerJob {
                 if let Err(e) = std::io::stdin().read_line(&mut input) {
                     error!(out, "Stdin input error: {}", e);
                 };
-                if let Err(_e) = sender.send(input.clone()) {
+                if let Err(_e) = sender.send(InputPacket::Command(input.clone(), None)) {
                     break 'main;
                 };
             }
@@ -259,3 +333,107 @@ impl StdinManagerJob {
 impl JobCleaner for StdinManagerJob {
     fn terminate(self: Box<Self>) {}
 }
+
+/****** Player tracking ******/
+
+/*
+    Polls the query port and reports players joining and leaving.
+    It's the only way to know it when the server stdout is not
+    available (RCON control)
+*/
+
+use crate::query::full_stat;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+const PLAYER_TRACKING_TIME: u64 = 30;
+
+struct PlayerTrackerJob {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl PlayerTrackerJob {
+    fn start(query_address: &str) -> PlayerTrackerJob {
+        let address = String::from(query_address);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_c = stop.clone();
+        let out = get_output_sender();
+        let handle = thread::spawn(move || {
+            let mut online: Option<Vec<String>> = None;
+            while !stop_c.load(Ordering::SeqCst) {
+                //Failed queries are ignored, the server could be restarting
+                if let Ok(stat) = full_stat(&address) {
+                    if let Some(previous) = &online {
+                        for p in stat.players.iter().filter(|p| !previous.contains(p)) {
+                            infoln!(out, "{} joined the server", p);
+                        }
+                        for p in previous.iter().filter(|p| !stat.players.contains(p)) {
+                            infoln!(out, "{} left the server", p);
+                        }
+                    }
+                    online = Some(stat.players);
+                }
+                thread::park_timeout(Duration::from_secs(PLAYER_TRACKING_TIME));
+            }
+        });
+        PlayerTrackerJob { stop, handle }
+    }
+}
+
+impl JobCleaner for PlayerTrackerJob {
+    fn terminate(self: Box<Self>) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.thread().unpark();
+        self.handle.join().unwrap();
+    }
+}
+
+/****** Crash report watching ******/
+
+/*
+    The server writes a report to crash-reports before dying.
+    Reports that were already there when the manager started
+    are ignored. The thread of a server that dies looks for
+    them too, so this job mostly finds the reports of servers
+    controlled through RCON
+*/
+
+use crate::crash::*;
+
+const CRASH_POLLING_TIME: u64 = 5;
+
+struct CrashWatcherJob {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl CrashWatcherJob {
+    fn start(config: &Config) -> CrashWatcherJob {
+        let server_path = config.server_path();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_c = stop.clone();
+        let out = get_output_sender();
+        ignore_existing_reports(&server_path);
+        let handle = thread::spawn(move || {
+            while !stop_c.load(Ordering::SeqCst) {
+                thread::park_timeout(Duration::from_secs(CRASH_POLLING_TIME));
+                send_new_reports(&server_path, &out, false);
+            }
+        });
+        CrashWatcherJob { stop, handle }
+    }
+}
+
+impl JobCleaner for CrashWatcherJob {
+    fn terminate(self: Box<Self>) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.thread().unpark();
+        self.handle.join().unwrap();
+    }
+}
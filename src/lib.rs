@@ -1,11 +1,34 @@
+pub mod aliases;
+pub mod announcements;
 pub mod backup;
+pub mod chat;
 pub mod config;
+pub mod crash;
+pub mod datapacks;
 pub mod error;
+pub mod eula;
+pub mod idle;
 pub mod io;
 pub mod jobs;
+pub mod lists;
+pub mod logs;
+pub mod maintenance;
+pub mod nbt;
+pub mod network;
+pub mod performance;
+pub mod permissions;
+pub mod ping;
+pub mod plugins;
 pub mod processes;
+pub mod properties;
+pub mod query;
+pub mod rcon;
+pub mod region;
+pub mod restart;
 pub mod server_handler;
 pub mod telegram;
+pub mod versions;
+pub mod world;
 
 pub use crate::config::*;
 pub use crate::error::*;
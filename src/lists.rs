@@ -0,0 +1,321 @@
+use crate::io::*;
+use crate::properties::ServerProperties;
+use crate::server_handler::ServerHandler;
+use crate::*;
+
+use chrono::Local;
+use serde_json::{json, Value};
+use std::{fs, net::IpAddr, thread, time::Duration};
+
+const USER_CACHE_FILE: &str = "usercache.json";
+const BAN_SOURCE: &str = "Server Manager";
+const DEFAULT_BAN_REASON: &str = "Banned by an operator.";
+const DEFAULT_OP_LEVEL: i64 = 4;
+
+//The server saves the lists as soon as they change
+const VERIFY_POLLING_TIME: u64 = 500;
+const VERIFY_TIME: u64 = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PlayerList {
+    Whitelist,
+    Ops,
+    BannedPlayers,
+    BannedIps,
+}
+
+impl PlayerList {
+    pub fn from_command(command: &str) -> Option<PlayerList> {
+        match command {
+            "whitelist" => Some(PlayerList::Whitelist),
+            "ops" => Some(PlayerList::Ops),
+            "bans" => Some(PlayerList::BannedPlayers),
+            "ipbans" => Some(PlayerList::BannedIps),
+            _ => None,
+        }
+    }
+
+    fn file(self) -> &'static str {
+        match self {
+            PlayerList::Whitelist => "whitelist.json",
+            PlayerList::Ops => "ops.json",
+            PlayerList::BannedPlayers => "banned-players.json",
+            PlayerList::BannedIps => "banned-ips.json",
+        }
+    }
+
+    fn command_name(self) -> &'static str {
+        match self {
+            PlayerList::Whitelist => "whitelist",
+            PlayerList::Ops => "ops",
+            PlayerList::BannedPlayers => "bans",
+            PlayerList::BannedIps => "ipbans",
+        }
+    }
+
+    //Field that identifies the entries of the list
+    fn key(self) -> &'static str {
+        match self {
+            PlayerList::BannedIps => "ip",
+            _ => "name",
+        }
+    }
+
+    fn add_command(self, target: &str, reason: &str) -> String {
+        match self {
+            PlayerList::Whitelist => format!("whitelist add {}", target),
+            PlayerList::Ops => format!("op {}", target),
+            PlayerList::BannedPlayers => format!("ban {} {}", target, reason),
+            PlayerList::BannedIps => format!("ban-ip {} {}", target, reason),
+        }
+    }
+
+    fn remove_command(self, target: &str) -> String {
+        match self {
+            PlayerList::Whitelist => format!("whitelist remove {}", target),
+            PlayerList::Ops => format!("deop {}", target),
+            PlayerList::BannedPlayers => format!("pardon {}", target),
+            PlayerList::BannedIps => format!("pardon-ip {}", target),
+        }
+    }
+}
+
+//ban-ip also takes the name of an online player, whose IP only the server knows
+fn is_ip(target: &str) -> bool {
+    target.parse::<IpAddr>().is_ok()
+}
+
+pub fn read_list(config: &Config, list: PlayerList) -> GenericResult<Vec<Value>> {
+    let path = config.server_path().join(list.file());
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&path)?;
+    match serde_json::from_str::<Value>(&content) {
+        Ok(Value::Array(entries)) => Ok(entries),
+        _ => Err(format!("{} is not a valid list", list.file()).into()),
+    }
+}
+
+fn write_list(config: &Config, list: PlayerList, entries: Vec<Value>) -> GenericResult<()> {
+    let path = config.server_path().join(list.file());
+    let content = serde_json::to_string_pretty(&Value::Array(entries))
+        .map_err(|e| GenericError::from(format!("{}", e)))?;
+    fs::write(path, content)?;
+    Ok(())
+}
+
+pub fn contains(entries: &[Value], list: PlayerList, target: &str) -> bool {
+    entries.iter().any(|e| matches(e, list, target))
+}
+
+fn matches(entry: &Value, list: PlayerList, target: &str) -> bool {
+    entry[list.key()]
+        .as_str()
+        .map(|v| v.eq_ignore_ascii_case(target))
+        .unwrap_or(false)
+}
+
+//Players must have joined the server once for their UUID to be known offline
+fn lookup_player(config: &Config, name: &str) -> GenericResult<(String, String)> {
+    let content = fs::read_to_string(config.server_path().join(USER_CACHE_FILE))?;
+    let cache: Value = serde_json::from_str(&content)
+        .map_err(|e| GenericError::from(format!("Invalid {}: {}", USER_CACHE_FILE, e)))?;
+    cache
+        .as_array()
+        .and_then(|players| {
+            players.iter().find(|p| {
+                p["name"]
+                    .as_str()
+                    .map(|n| n.eq_ignore_ascii_case(name))
+                    .unwrap_or(false)
+            })
+        })
+        .and_then(|p| Some((p["uuid"].as_str()?.to_string(), p["name"].as_str()?.to_string())))
+        .ok_or_else(|| {
+            format!(
+                "{} is not in {}. The player must join the server once",
+                name, USER_CACHE_FILE
+            )
+            .into()
+        })
+}
+
+fn new_entry(config: &Config, list: PlayerList, target: &str, reason: &str) -> GenericResult<Value> {
+    let created = Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string();
+    if list == PlayerList::BannedIps {
+        return Ok(json!({
+            "ip": target,
+            "created": created,
+            "source": BAN_SOURCE,
+            "expires": "forever",
+            "reason": reason,
+        }));
+    }
+
+    let (uuid, name) = lookup_player(config, target)?;
+    Ok(match list {
+        PlayerList::Ops => {
+            let level = ServerProperties::load(&config.server_path())
+                .ok()
+                .and_then(|p| p.get("op-permission-level").and_then(|l| l.parse().ok()))
+                .unwrap_or(DEFAULT_OP_LEVEL);
+            json!({
+                "uuid": uuid,
+                "name": name,
+                "level": level,
+                "bypassesPlayerLimit": false,
+            })
+        }
+        PlayerList::BannedPlayers => json!({
+            "uuid": uuid,
+            "name": name,
+            "created": created,
+            "source": BAN_SOURCE,
+            "expires": "forever",
+            "reason": reason,
+        }),
+        _ => json!({ "uuid": uuid, "name": name }),
+    })
+}
+
+/*
+    <list> list: shows the list
+    <list> add <name|ip> [reason]: adds an entry
+    <list> remove <name|ip>: removes an entry
+
+    Running servers are changed with their own commands, because they
+    overwrite the files with the lists they have in memory. Stopped
+    servers get their files edited. Either way, the user needs to be
+    allowed to run the server command
+*/
+pub fn list_command(
+    list: PlayerList,
+    args: &[&str],
+    config: &Config,
+    server: Option<&mut ServerHandler>,
+    user: Option<i64>,
+) {
+    let out = get_output_sender();
+    let reason = match args {
+        ["add", _, reason @ ..] if !reason.is_empty() => reason.join(" "),
+        _ => String::from(DEFAULT_BAN_REASON),
+    };
+    let console_command = match args {
+        ["add", target, ..] => Some(list.add_command(target, &reason)),
+        ["remove", target] => Some(list.remove_command(target)),
+        [] | ["list"] => None,
+        _ if list == PlayerList::Whitelist => Some(format!("whitelist {}", args.join(" "))),
+        _ => None,
+    };
+    if let Some(command) = console_command {
+        let by = format!("{} {}", list.command_name(), args.join(" "));
+        if !config.telegram_permissions.allows(user, &command, &by) {
+            return;
+        }
+    }
+    let result = match (args, server) {
+        (["list"], _) => read_list(config, list).map(|entries| {
+            let names: Vec<&str> = entries.iter().filter_map(|e| e[list.key()].as_str()).collect();
+            infoln!(out, "{} ({}): {}", list.file(), names.len(), names.join(", "));
+        }),
+        (["add", target, ..], None) if list == PlayerList::BannedIps && !is_ip(target) => Err(
+            format!("The IP of {} is only known while the server is running", target).into(),
+        ),
+        (["remove", target], _) if list == PlayerList::BannedIps && !is_ip(target) => {
+            Err(format!("{} is not an IP", target).into())
+        }
+        //The new entry has the IP of the player, so it can only be told by the size of the list
+        (["add", target, ..], Some(server)) if list == PlayerList::BannedIps && !is_ip(target) => {
+            read_list(config, list).and_then(|entries| {
+                server.sendln(list.add_command(target, &reason).as_bytes())?;
+                verify(config, list, target, true, |e| e.len() > entries.len())
+            })
+        }
+        (["add", target, ..], Some(server)) => server
+            .sendln(list.add_command(target, &reason).as_bytes())
+            .and_then(|_| verify(config, list, target, true, |e| contains(e, list, target))),
+        (["remove", target], Some(server)) => server
+            .sendln(list.remove_command(target).as_bytes())
+            .and_then(|_| verify(config, list, target, false, |e| !contains(e, list, target))),
+        (["add", target, ..], None) => add_offline(config, list, target, &reason),
+        (["remove", target], None) => remove_offline(config, list, target),
+        //Other whitelist subcommands (on, off, reload) are left to the server
+        (_, Some(server)) if list == PlayerList::Whitelist && !args.is_empty() => {
+            server.sendln(format!("whitelist {}", args.join(" ")).as_bytes())
+        }
+        _ => {
+            warnln!(
+                out,
+                "Usage: {} list | {} add <{}> | {} remove <{}>",
+                list.command_name(),
+                list.command_name(),
+                list.key(),
+                list.command_name(),
+                list.key()
+            );
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        errorln!(out, "{}", e);
+    }
+}
+
+fn verify<F: Fn(&[Value]) -> bool>(
+    config: &Config,
+    list: PlayerList,
+    target: &str,
+    added: bool,
+    changed: F,
+) -> GenericResult<()> {
+    let out = get_output_sender();
+    for _ in 0..(VERIFY_TIME * 1000 / VERIFY_POLLING_TIME) {
+        thread::sleep(Duration::from_millis(VERIFY_POLLING_TIME));
+        if changed(&read_list(config, list)?) {
+            infoln!(
+                out,
+                "{} {} {}",
+                target,
+                if added { "added to" } else { "removed from" },
+                list.file()
+            );
+            return Ok(());
+        }
+    }
+    Err(format!(
+        "The server didn't {} {} {}. Check its output",
+        if added { "add" } else { "remove" },
+        target,
+        if added { "to the list" } else { "from the list" }
+    )
+    .into())
+}
+
+fn add_offline(config: &Config, list: PlayerList, target: &str, reason: &str) -> GenericResult<()> {
+    let out = get_output_sender();
+    let mut entries = read_list(config, list)?;
+    if contains(&entries, list, target) {
+        warnln!(out, "{} is already in {}", target, list.file());
+        return Ok(());
+    }
+    entries.push(new_entry(config, list, target, reason)?);
+    write_list(config, list, entries)?;
+    infoln!(out, "{} added to {}", target, list.file());
+    Ok(())
+}
+
+fn remove_offline(config: &Config, list: PlayerList, target: &str) -> GenericResult<()> {
+    let out = get_output_sender();
+    let mut entries = read_list(config, list)?;
+    let length = entries.len();
+    entries.retain(|e| !matches(e, list, target));
+    if entries.len() == length {
+        warnln!(out, "{} is not in {}", target, list.file());
+        return Ok(());
+    }
+    write_list(config, list, entries)?;
+    infoln!(out, "{} removed from {}", target, list.file());
+    Ok(())
+}
@@ -0,0 +1,316 @@
+use crate::io::*;
+use crate::restart::parse_duration;
+use crate::*;
+
+use chrono::{DateTime, Duration as ChronoDuration, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use flate2::read::GzDecoder;
+use regex::Regex;
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+/*
+    The server writes logs/latest.log and compresses it as
+    logs/<YYYY-MM-DD>-<N>.log.gz when it starts again or the day changes.
+    Lines only have the time ([HH:MM:SS]). The name of a compressed log
+    (or the modification date of latest.log) is the date of its last line,
+    and a day goes by every time the time goes back
+*/
+
+const LOGS_DIRECTORY: &str = "logs";
+const LATEST_LOG: &str = "latest.log";
+const ROTATED_LOG_EXTENSION: &str = ".log.gz";
+//Matches kept (the newest ones), shown in pages small enough for a chat message
+const MAX_MATCHES: usize = 1000;
+const PAGE_SIZE: usize = 15;
+const MAX_LINE_LENGTH: usize = 200;
+const USAGE: &str = "Usage: logs search <regex> [--since <date>] [--until <date>] [--page <number>]";
+
+struct LogMatch {
+    time: NaiveDateTime,
+    file: String,
+    line: String,
+}
+
+//"[HH:MM:SS] [Server thread/INFO]: ..." -> (time, rest of the line)
+fn split_time(line: &str) -> Option<(NaiveTime, &str)> {
+    let rest = line.strip_prefix('[')?;
+    let (time, rest) = rest.split_once(']')?;
+    let time = NaiveTime::parse_from_str(time, "%H:%M:%S").ok()?;
+    Some((time, rest.trim_start()))
+}
+
+//Matching lines with the days passed since the first line and their time, and the days in the file
+type LineMatches = (Vec<(u32, NaiveTime, String)>, u32);
+
+//Lines without time (stack traces) take the time of the line above
+fn search_lines<R: BufRead>(reader: R, pattern: &Regex) -> GenericResult<LineMatches> {
+    let mut matches = Vec::new();
+    let (mut days, mut last) = (0, None);
+    let mut time = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+    for line in reader.lines() {
+        //The logs may have characters that are not UTF-8
+        let line = match line {
+            Ok(l) => l,
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let text = match split_time(&line) {
+            Some((t, text)) => {
+                if last.is_some_and(|l| t < l) {
+                    days += 1;
+                }
+                last = Some(t);
+                time = t;
+                text
+            }
+            None => line.as_str(),
+        };
+        if pattern.is_match(text) {
+            let text = match text.char_indices().nth(MAX_LINE_LENGTH) {
+                Some((i, _)) => format!("{}...", &text[..i]),
+                None => String::from(text),
+            };
+            matches.push((days, time, text));
+        }
+    }
+    Ok((matches, days))
+}
+
+fn search_file(path: &Path, pattern: &Regex) -> GenericResult<Vec<LogMatch>> {
+    let file = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let (reader, last_date): (Box<dyn Read>, NaiveDate) = if file == LATEST_LOG {
+        let modified: DateTime<Local> = fs::metadata(path)?.modified()?.into();
+        (Box::new(File::open(path)?), modified.date_naive())
+    } else {
+        let date = file_date(&file).ok_or_else(|| GenericError::from(format!("{} has no date", file)))?;
+        (Box::new(GzDecoder::new(File::open(path)?)), date)
+    };
+    let (matches, total_days) = search_lines(BufReader::new(reader), pattern)?;
+    let first_date = last_date - ChronoDuration::days(i64::from(total_days));
+    Ok(matches
+        .into_iter()
+        .map(|(days, time, line)| LogMatch {
+            time: (first_date + ChronoDuration::days(i64::from(days))).and_time(time),
+            file: file.clone(),
+            line,
+        })
+        .collect())
+}
+
+//"2024-05-01-1.log.gz" -> 2024-05-01
+fn file_date(name: &str) -> Option<NaiveDate> {
+    name.get(..10)
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+}
+
+//Rotated logs that end before the start of the search can be skipped
+fn log_files(logs_path: &Path, since: Option<NaiveDateTime>) -> GenericResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(logs_path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        if name == LATEST_LOG {
+            files.push(path);
+        } else if name.ends_with(ROTATED_LOG_EXTENSION) {
+            match (file_date(&name), since) {
+                (Some(date), Some(since)) if date < since.date() => (),
+                _ => files.push(path),
+            }
+        }
+    }
+    Ok(files)
+}
+
+//"2d", "12h"... (before now), "YYYY-MM-DD" or "YYYY-MM-DDTHH:MM"
+fn parse_time(s: &str, end_of_day: bool) -> GenericResult<NaiveDateTime> {
+    let now = Local::now().naive_local();
+    if let Some(secs) = parse_duration(s) {
+        return Ok(now - ChronoDuration::seconds(secs as i64));
+    }
+    if let Ok(time) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M") {
+        return Ok(time);
+    }
+    match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        Ok(date) if end_of_day => Ok(date.and_hms_opt(23, 59, 59).unwrap()),
+        Ok(date) => Ok(date.and_hms_opt(0, 0, 0).unwrap()),
+        Err(_) => Err(format!("Invalid date: {}. Use 2d, 12h, YYYY-MM-DD or YYYY-MM-DDTHH:MM", s).into()),
+    }
+}
+
+struct SearchOptions {
+    pattern: String,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    page: usize,
+}
+
+fn parse_page(s: &str) -> GenericResult<usize> {
+    match s.parse() {
+        Ok(page) if page >= 1 => Ok(page),
+        _ => Err(format!("Invalid page: {}", s).into()),
+    }
+}
+
+//Everything that is not an option is part of the pattern
+fn parse_options(args: &[&str]) -> GenericResult<SearchOptions> {
+    let mut options = SearchOptions {
+        pattern: String::new(),
+        since: None,
+        until: None,
+        page: 1,
+    };
+    let mut words = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            option @ ("--since" | "--until" | "--page") => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| GenericError::from(format!("{} needs a value", option)))?;
+                match option {
+                    "--since" => options.since = Some(parse_time(value, false)?),
+                    "--until" => options.until = Some(parse_time(value, true)?),
+                    _ => options.page = parse_page(value)?,
+                }
+            }
+            word => words.push(word),
+        }
+    }
+    options.pattern = words.join(" ");
+    Ok(options)
+}
+
+fn search(config: &Config, args: &[&str]) -> GenericResult<()> {
+    let out = get_output_sender();
+    let options = parse_options(args)?;
+    if options.pattern.is_empty() {
+        return Err(USAGE.into());
+    }
+    let pattern = Regex::new(&options.pattern).map_err(|e| GenericError::from(format!("Invalid regex: {}", e)))?;
+    let logs_path = config.server_path().join(LOGS_DIRECTORY);
+    if !logs_path.is_dir() {
+        return Err(format!("{} doesn't exist", logs_path.display()).into());
+    }
+
+    let mut matches = Vec::new();
+    for path in log_files(&logs_path, options.since)? {
+        match search_file(&path, &pattern) {
+            Ok(m) => matches.extend(m),
+            Err(e) => {
+                warnln!(out, "Could not read {}: {}", path.display(), e);
+            }
+        }
+    }
+    matches.retain(|m| {
+        options.since.is_none_or(|s| m.time >= s) && options.until.is_none_or(|u| m.time <= u)
+    });
+    if matches.is_empty() {
+        infoln!(out, "No lines match {}", options.pattern);
+        return Ok(());
+    }
+
+    //Newest first
+    matches.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| b.file.cmp(&a.file)));
+    let total = matches.len();
+    matches.truncate(MAX_MATCHES);
+    let pages = matches.len().div_ceil(PAGE_SIZE);
+    if options.page > pages {
+        return Err(format!("There are only {} pages", pages).into());
+    }
+    let mut s = if total > MAX_MATCHES {
+        format!(
+            "{} lines match, showing the newest {}. Page {}/{}:",
+            total, MAX_MATCHES, options.page, pages
+        )
+    } else {
+        format!("{} lines match. Page {}/{}:", total, options.page, pages)
+    };
+    for m in matches.iter().skip((options.page - 1) * PAGE_SIZE).take(PAGE_SIZE) {
+        s.push_str(&format!("\n{} ({}) {}", m.time.format("%Y-%m-%d %H:%M:%S"), m.file, m.line));
+    }
+    if options.page < pages {
+        s.push_str(&format!("\nNext page: add --page {}", options.page + 1));
+    }
+    infoln!(out, "{}", s);
+    Ok(())
+}
+
+/*
+    logs search <regex> [--since <date>] [--until <date>] [--page <number>]:
+    searches latest.log and the compressed logs of the server
+*/
+pub fn logs_command(args: &[&str], config: &Config) {
+    let out = get_output_sender();
+    let result = match args {
+        ["search", args @ ..] => search(config, args),
+        _ => Err(USAGE.into()),
+    };
+
+    if let Err(e) = result {
+        errorln!(out, "{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32, s: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, s).unwrap()
+    }
+
+    #[test]
+    fn splits_the_time() {
+        assert_eq!(
+            split_time("[23:59:01] [Server thread/INFO]: Done"),
+            Some((time(23, 59, 1), "[Server thread/INFO]: Done"))
+        );
+        assert_eq!(split_time("[23:59:01 INFO]: Done"), None);
+        assert_eq!(split_time("\tat java.lang.Thread.run"), None);
+        assert_eq!(file_date("2024-05-01-3.log.gz"), NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(file_date("latest.log"), None);
+    }
+
+    #[test]
+    fn counts_the_days_of_a_file() {
+        let log = "[23:00:00] [Server thread/INFO]: Steve joined the game\n\
+                   [23:30:00] [Server thread/ERROR]: Steve crashed\n\
+                   \tat some.Class\n\
+                   [00:10:00] [Server thread/INFO]: Steve left the game\n\
+                   [00:05:00] [Server thread/INFO]: Steve joined the game\n";
+        let pattern = Regex::new("Steve|some").unwrap();
+        let (matches, days) = search_lines(log.as_bytes(), &pattern).unwrap();
+        assert_eq!(days, 2);
+        let matches: Vec<(u32, NaiveTime)> = matches.iter().map(|(d, t, _)| (*d, *t)).collect();
+        assert_eq!(
+            matches,
+            vec![
+                (0, time(23, 0, 0)),
+                (0, time(23, 30, 0)),
+                (0, time(23, 30, 0)),
+                (1, time(0, 10, 0)),
+                (2, time(0, 5, 0)),
+            ]
+        );
+        let long = format!("[10:00:00] [Server thread/INFO]: {}", "a".repeat(MAX_LINE_LENGTH + 10));
+        let (matches, _) = search_lines(long.as_bytes(), &Regex::new("a{10}").unwrap()).unwrap();
+        assert_eq!(matches[0].2.len(), MAX_LINE_LENGTH + 3);
+    }
+
+    #[test]
+    fn parses_the_options() {
+        let options = parse_options(&["joined", "the", "--page", "2", "game", "--since", "2024-05-01"]).unwrap();
+        assert_eq!(options.pattern, "joined the game");
+        assert_eq!(options.page, 2);
+        assert_eq!(options.since, NaiveDate::from_ymd_opt(2024, 5, 1).and_then(|d| d.and_hms_opt(0, 0, 0)));
+        let options = parse_options(&["x", "--until", "2024-05-01"]).unwrap();
+        assert_eq!(options.until, NaiveDate::from_ymd_opt(2024, 5, 1).and_then(|d| d.and_hms_opt(23, 59, 59)));
+        assert!(parse_options(&["x", "--page", "0"]).is_err());
+        assert!(parse_options(&["x", "--since"]).is_err());
+        assert!(parse_options(&["x", "--since", "yesterday"]).is_err());
+        assert!(parse_time("2d", false).unwrap() < Local::now().naive_local() - ChronoDuration::hours(47));
+    }
+}
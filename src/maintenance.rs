@@ -0,0 +1,164 @@
+use crate::io::*;
+use crate::lists::*;
+use crate::properties::*;
+use crate::server_handler::ServerHandler;
+use crate::*;
+
+use serde_json::{json, Map, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/*
+    While the maintenance file exists the manager doesn't start or
+    restart the server by itself. The file keeps the properties changed
+    by maintenance mode so they are restored exactly when it ends
+*/
+
+const MAINTENANCE_FILE: &str = ".maintenance.json";
+const DEFAULT_REASON: &str = "The server is under maintenance";
+const MAINTENANCE_MOTD: &str = "Under maintenance";
+const SAVED_PROPERTIES: [&str; 3] = ["white-list", "enforce-whitelist", "motd"];
+const WHITELIST_ANSWER: &str = "Whitelist is";
+const PLAYERS_ANSWER: &str = "players online:";
+
+fn maintenance_file(server_path: &Path) -> PathBuf {
+    server_path.join(MAINTENANCE_FILE)
+}
+
+//Takes the server directory so that it can be checked from other threads
+pub fn in_maintenance(server_path: &Path) -> bool {
+    maintenance_file(server_path).exists()
+}
+
+fn load_state(config: &Config) -> GenericResult<Value> {
+    let content = fs::read_to_string(maintenance_file(&config.server_path()))
+        .map_err(|_| GenericError::from("Maintenance mode is off"))?;
+    serde_json::from_str(&content)
+        .map_err(|e| GenericError::from(format!("Invalid {}: {}", MAINTENANCE_FILE, e)))
+}
+
+fn enable(config: &Config, reason: &str, server: Option<&mut ServerHandler>) -> GenericResult<()> {
+    let server_path = config.server_path();
+    if in_maintenance(&server_path) {
+        return Err("Maintenance mode is already on".into());
+    }
+    let properties = ServerProperties::load(&server_path)?;
+    let previous: Map<String, Value> = SAVED_PROPERTIES
+        .iter()
+        .map(|k| (String::from(*k), json!(properties.get(k))))
+        .collect();
+    let state = json!({ "reason": reason, "properties": previous });
+    fs::write(maintenance_file(&server_path), state.to_string())?;
+
+    //The server writes server.properties when the whitelist changes
+    let result = match server {
+        Some(server) => server
+            .query("whitelist on", &[WHITELIST_ANSWER])
+            .and_then(|_| kick_players(server, config, reason)),
+        None => Ok(()),
+    }
+    .and_then(|_| {
+        let mut properties = ServerProperties::load(&server_path)?;
+        properties.set("white-list", "true");
+        properties.set("enforce-whitelist", "true");
+        properties.set("motd", &format!("{} - {}", MAINTENANCE_MOTD, reason));
+        properties.save()
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(maintenance_file(&server_path));
+    }
+    result
+}
+
+fn disable(config: &Config, server: Option<&mut ServerHandler>) -> GenericResult<()> {
+    let server_path = config.server_path();
+    let state = load_state(config)?;
+    let previous = state["properties"]
+        .as_object()
+        .ok_or("The maintenance file has no properties")?;
+
+    if let Some(server) = server {
+        if previous.get("white-list").and_then(Value::as_str) != Some("true") {
+            server.query("whitelist off", &[WHITELIST_ANSWER])?;
+        }
+    }
+    let mut properties = ServerProperties::load(&server_path)?;
+    for (key, value) in previous {
+        match value.as_str() {
+            Some(value) => properties.set(key, value),
+            None => properties.remove(key),
+        }
+    }
+    properties.save()?;
+    fs::remove_file(maintenance_file(&server_path))?;
+    Ok(())
+}
+
+//Operators can stay
+fn kick_players(server: &mut ServerHandler, config: &Config, reason: &str) -> GenericResult<()> {
+    let out = get_output_sender();
+    let answer = server.query("list", &[PLAYERS_ANSWER])?;
+    let ops = read_list(config, PlayerList::Ops)?;
+    let players = answer
+        .split_once(PLAYERS_ANSWER)
+        .map(|(_, names)| names.lines().next().unwrap_or(""))
+        .unwrap_or("");
+    for player in players.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        if !contains(&ops, PlayerList::Ops, player) {
+            server.sendln(format!("kick {} {}", player, reason).as_bytes())?;
+            infoln!(out, "{} kicked", player);
+        }
+    }
+    Ok(())
+}
+
+/*
+    maintenance: shows whether maintenance mode is on
+    maintenance on [reason]: enables the whitelist, kicks the players that
+    are not operators and changes the MOTD
+    maintenance off: restores the whitelist and the MOTD
+*/
+pub fn maintenance_command(args: &[&str], config: &Config, server: Option<&mut ServerHandler>) {
+    let out = get_output_sender();
+    let result = match args {
+        [] | ["status"] => match load_state(config) {
+            Ok(state) => {
+                infoln!(
+                    out,
+                    "Maintenance mode is on: {}",
+                    state["reason"].as_str().unwrap_or(DEFAULT_REASON)
+                );
+                Ok(())
+            }
+            Err(_) => {
+                infoln!(out, "Maintenance mode is off");
+                Ok(())
+            }
+        },
+        ["on", reason @ ..] => {
+            let reason = match reason.join(" ") {
+                r if r.is_empty() => String::from(DEFAULT_REASON),
+                r => r,
+            };
+            enable(config, &reason, server).map(|_| {
+                infoln!(
+                    out,
+                    "Maintenance mode is on. The server won't be started or restarted automatically"
+                );
+            })
+        }
+        ["off"] => disable(config, server).map(|_| {
+            infoln!(out, "Maintenance mode is off");
+        }),
+        _ => {
+            warnln!(out, "Usage: maintenance [on [reason] | off]");
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        errorln!(out, "{}", e);
+    }
+}
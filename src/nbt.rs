@@ -0,0 +1,310 @@
+use crate::*;
+
+use flate2::read::GzDecoder;
+use std::{collections::HashMap, fs::File, io::Read, path::Path};
+
+/*
+    Named Binary Tag, the format of the files of the worlds.
+    Everything is big endian and strings are prefixed by their length
+*/
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+//Corrupted files could make us allocate huge arrays
+const MAX_ARRAY_LENGTH: i32 = 1 << 24;
+const MAX_DEPTH: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    //Follows a path of compound keys
+    pub fn get_path(&self, path: &[&str]) -> Option<&Tag> {
+        path.iter().try_fold(self, |tag, key| tag.get(key))
+    }
+
+    //Any integer, as booleans are stored as bytes
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Tag::Byte(v) => Some(*v as i64),
+            Tag::Short(v) => Some(*v as i64),
+            Tag::Int(v) => Some(*v as i64),
+            Tag::Long(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        self.as_i64().map(|v| v != 0)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_int_array(&self) -> Option<&[i32]> {
+        match self {
+            Tag::IntArray(array) => Some(array),
+            _ => None,
+        }
+    }
+}
+
+//Reads the root tag, which is always a named compound
+pub fn read_nbt<R: Read>(reader: &mut R) -> GenericResult<Tag> {
+    let id = read_u8(reader)?;
+    if id != TAG_COMPOUND {
+        return Err(format!("The root tag is not a compound ({})", id).into());
+    }
+    read_string(reader)?;
+    read_payload(reader, id, 0)
+}
+
+pub fn read_gzip_nbt(path: &Path) -> GenericResult<Tag> {
+    let mut decoder = GzDecoder::new(File::open(path)?);
+    read_nbt(&mut decoder)
+}
+
+fn read_payload<R: Read>(reader: &mut R, id: u8, depth: usize) -> GenericResult<Tag> {
+    if depth > MAX_DEPTH {
+        return Err("NBT is nested too deep".into());
+    }
+    Ok(match id {
+        TAG_BYTE => Tag::Byte(read_u8(reader)? as i8),
+        TAG_SHORT => Tag::Short(i16::from_be_bytes(read_array(reader)?)),
+        TAG_INT => Tag::Int(read_i32(reader)?),
+        TAG_LONG => Tag::Long(i64::from_be_bytes(read_array(reader)?)),
+        TAG_FLOAT => Tag::Float(f32::from_be_bytes(read_array(reader)?)),
+        TAG_DOUBLE => Tag::Double(f64::from_be_bytes(read_array(reader)?)),
+        TAG_BYTE_ARRAY => {
+            let length = read_length(reader)?;
+            let mut bytes = vec![0; length];
+            reader.read_exact(&mut bytes)?;
+            Tag::ByteArray(bytes.into_iter().map(|b| b as i8).collect())
+        }
+        TAG_STRING => Tag::String(read_string(reader)?),
+        TAG_LIST => {
+            let item_id = read_u8(reader)?;
+            let length = read_length(reader)?;
+            let mut list = Vec::with_capacity(length.min(1024));
+            //Empty lists can have the end tag as their type
+            if item_id != TAG_END {
+                for _ in 0..length {
+                    list.push(read_payload(reader, item_id, depth + 1)?);
+                }
+            }
+            Tag::List(list)
+        }
+        TAG_COMPOUND => {
+            let mut map = HashMap::new();
+            loop {
+                let item_id = read_u8(reader)?;
+                if item_id == TAG_END {
+                    break;
+                }
+                let name = read_string(reader)?;
+                map.insert(name, read_payload(reader, item_id, depth + 1)?);
+            }
+            Tag::Compound(map)
+        }
+        TAG_INT_ARRAY => {
+            let length = read_length(reader)?;
+            let mut array = Vec::with_capacity(length);
+            for _ in 0..length {
+                array.push(read_i32(reader)?);
+            }
+            Tag::IntArray(array)
+        }
+        TAG_LONG_ARRAY => {
+            let length = read_length(reader)?;
+            let mut array = Vec::with_capacity(length);
+            for _ in 0..length {
+                array.push(i64::from_be_bytes(read_array(reader)?));
+            }
+            Tag::LongArray(array)
+        }
+        _ => return Err(format!("Unknown NBT tag {}", id).into()),
+    })
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> GenericResult<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> GenericResult<u8> {
+    Ok(read_array::<R, 1>(reader)?[0])
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> GenericResult<i32> {
+    Ok(i32::from_be_bytes(read_array(reader)?))
+}
+
+fn read_length<R: Read>(reader: &mut R) -> GenericResult<usize> {
+    let length = read_i32(reader)?;
+    if !(0..=MAX_ARRAY_LENGTH).contains(&length) {
+        return Err(format!("Invalid NBT length {}", length).into());
+    }
+    Ok(length as usize)
+}
+
+//Java's modified UTF-8 only differs for null and supplementary characters
+fn read_string<R: Read>(reader: &mut R) -> GenericResult<String> {
+    let length = u16::from_be_bytes(read_array(reader)?) as usize;
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::{env, fs, io::Write, process};
+
+    fn named(id: u8, name: &str) -> Vec<u8> {
+        let mut bytes = vec![id];
+        bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes
+    }
+
+    //level.dat-like data with every kind of tag
+    fn level() -> Vec<u8> {
+        let mut bytes = named(TAG_COMPOUND, "");
+        bytes.extend(named(TAG_COMPOUND, "Data"));
+        bytes.extend(named(TAG_STRING, "LevelName"));
+        bytes.extend_from_slice(&[0, 5]);
+        bytes.extend_from_slice(b"world");
+        bytes.extend(named(TAG_BYTE, "hardcore"));
+        bytes.push(1);
+        bytes.extend(named(TAG_SHORT, "short"));
+        bytes.extend_from_slice(&(-2i16).to_be_bytes());
+        bytes.extend(named(TAG_INT, "DataVersion"));
+        bytes.extend_from_slice(&3465i32.to_be_bytes());
+        bytes.extend(named(TAG_LONG, "Time"));
+        bytes.extend_from_slice(&(1i64 << 40).to_be_bytes());
+        bytes.extend(named(TAG_FLOAT, "float"));
+        bytes.extend_from_slice(&1.5f32.to_be_bytes());
+        bytes.extend(named(TAG_DOUBLE, "double"));
+        bytes.extend_from_slice(&(-0.25f64).to_be_bytes());
+        bytes.extend(named(TAG_BYTE_ARRAY, "bytes"));
+        bytes.extend_from_slice(&[0, 0, 0, 2, 0xff, 1]);
+        bytes.extend(named(TAG_INT_ARRAY, "ints"));
+        bytes.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 7]);
+        bytes.extend(named(TAG_LONG_ARRAY, "longs"));
+        bytes.extend_from_slice(&[0, 0, 0, 1]);
+        bytes.extend_from_slice(&9i64.to_be_bytes());
+        bytes.extend(named(TAG_LIST, "names"));
+        bytes.extend_from_slice(&[TAG_STRING, 0, 0, 0, 2, 0, 1, b'a', 0, 1, b'b']);
+        bytes.extend(named(TAG_LIST, "empty"));
+        bytes.extend_from_slice(&[TAG_END, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[TAG_END, TAG_END]);
+        bytes
+    }
+
+    #[test]
+    fn reads_every_tag() {
+        let tag = read_nbt(&mut level().as_slice()).unwrap();
+        let data = tag.get("Data").unwrap();
+        assert_eq!(tag.get_path(&["Data", "LevelName"]).and_then(Tag::as_str), Some("world"));
+        assert_eq!(data.get("hardcore").and_then(Tag::as_bool), Some(true));
+        assert_eq!(data.get("short").and_then(Tag::as_i64), Some(-2));
+        assert_eq!(data.get("DataVersion").and_then(Tag::as_i64), Some(3465));
+        assert_eq!(data.get("Time").and_then(Tag::as_i64), Some(1 << 40));
+        assert_eq!(data.get("float"), Some(&Tag::Float(1.5)));
+        assert_eq!(data.get("double"), Some(&Tag::Double(-0.25)));
+        assert_eq!(data.get("bytes"), Some(&Tag::ByteArray(vec![-1, 1])));
+        assert_eq!(data.get("ints").and_then(Tag::as_int_array), Some(&[7][..]));
+        assert_eq!(data.get("longs"), Some(&Tag::LongArray(vec![9])));
+        assert_eq!(
+            data.get("names").and_then(Tag::as_list),
+            Some(&[Tag::String(String::from("a")), Tag::String(String::from("b"))][..])
+        );
+        assert_eq!(data.get("empty").and_then(Tag::as_list), Some(&[][..]));
+        assert_eq!(data.get("LevelName").and_then(Tag::as_i64), None);
+    }
+
+    #[test]
+    fn reads_gzip_files() {
+        let path = env::temp_dir().join(format!("server_manager_level_{}.dat", process::id()));
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&level()).unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+        let tag = read_gzip_nbt(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(tag.unwrap().get_path(&["Data", "DataVersion"]), Some(&Tag::Int(3465)));
+    }
+
+    #[test]
+    fn rejects_invalid_data() {
+        assert!(read_nbt(&mut named(TAG_LIST, "").as_slice()).is_err());
+        //Truncated
+        let bytes = level();
+        assert!(read_nbt(&mut &bytes[..bytes.len() - 1]).is_err());
+        //Negative and huge lengths
+        let mut bytes = named(TAG_COMPOUND, "");
+        bytes.extend(named(TAG_INT_ARRAY, "ints"));
+        bytes.extend_from_slice(&(-1i32).to_be_bytes());
+        assert!(read_nbt(&mut bytes.as_slice()).is_err());
+        let mut bytes = named(TAG_COMPOUND, "");
+        bytes.extend(named(TAG_BYTE_ARRAY, "bytes"));
+        bytes.extend_from_slice(&i32::MAX.to_be_bytes());
+        assert!(read_nbt(&mut bytes.as_slice()).is_err());
+        //Unknown tag
+        let mut bytes = named(TAG_COMPOUND, "");
+        bytes.extend(named(13, "new"));
+        assert!(read_nbt(&mut bytes.as_slice()).is_err());
+        //Nested too deep
+        let mut bytes = named(TAG_COMPOUND, "");
+        for _ in 0..=MAX_DEPTH {
+            bytes.extend(named(TAG_COMPOUND, ""));
+        }
+        bytes.extend(vec![TAG_END; MAX_DEPTH + 2]);
+        let error = read_nbt(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(error.to_string(), "NBT is nested too deep");
+    }
+}
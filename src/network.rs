@@ -0,0 +1,89 @@
+use crate::eula::eula_accepted;
+use crate::io::*;
+use crate::maintenance::in_maintenance;
+use crate::ping::*;
+use crate::*;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/*
+    Proxies (Velocity, BungeeCord) send the players to the backends,
+    so they are started once the backends accept connections and
+    stopped before them
+*/
+
+pub const PROXY_STOP_COMMAND: &[u8] = b"end\n";
+const BACKEND_READY_TIME: u64 = 60 * 5;
+
+//Backends in maintenance or without the EULA accepted are not started by the manager
+fn will_start(backend: &Config) -> bool {
+    backend.control == ControlMode::Rcon
+        || (!in_maintenance(&backend.server_path()) && eula_accepted(backend))
+}
+
+//Sends "@proxy start" for every proxy when the backends are ready
+pub fn start_proxies_when_ready(backends: &[Config], proxies: &[Config]) {
+    let out = get_output_sender();
+    let input = get_input_sender();
+    let backends: Vec<(String, String)> = backends
+        .iter()
+        .filter(|b| will_start(b))
+        .map(|b| (b.name.clone(), b.server_address.clone()))
+        .collect();
+    let proxies: Vec<String> = proxies.iter().map(|p| p.name.clone()).collect();
+    thread::spawn(move || {
+        //They are all starting at the same time, so they share the deadline
+        let deadline = Instant::now() + Duration::from_secs(BACKEND_READY_TIME);
+        for (name, address) in &backends {
+            let timeout = deadline.saturating_duration_since(Instant::now()).as_secs();
+            if let Err(e) = wait_until_ready(address, timeout) {
+                warnln!(out, "{} is not ready, starting the proxy anyway: {}", name, e);
+            }
+        }
+        for proxy in proxies {
+            infoln!(out, "Backends are ready. Starting {}", proxy);
+            let _ = input.send(InputPacket::Command(format!("@{} start\n", proxy), None));
+        }
+    });
+}
+
+/*
+    network: shows the players of every server. The players of the network
+    are the ones connected to the proxies, or to the backends if there are none
+*/
+pub fn network_command(servers: &[&Config]) {
+    let out = get_output_sender();
+    let mut s = String::from("Network:");
+    let (mut proxy_players, mut backend_players) = (None, 0);
+    for server in servers {
+        let role = match server.role {
+            ServerRole::Proxy => "proxy",
+            ServerRole::Backend => "backend",
+        };
+        match ping(&server.server_address) {
+            Ok(status) => {
+                s.push_str(&format!(
+                    "\n{} ({}): {}/{} players",
+                    server.name, role, status.online_players, status.max_players
+                ));
+                match server.role {
+                    ServerRole::Proxy => {
+                        proxy_players = Some(proxy_players.unwrap_or(0) + status.online_players)
+                    }
+                    ServerRole::Backend => backend_players += status.online_players,
+                }
+            }
+            Err(_) => {
+                s.push_str(&format!("\n{} ({}): offline", server.name, role));
+            }
+        }
+    }
+    s.push_str(&format!(
+        "\nPlayers in the network: {}",
+        proxy_players.unwrap_or(backend_players)
+    ));
+    infoln!(out, "{}", s);
+}
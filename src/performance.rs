@@ -0,0 +1,409 @@
+use crate::io::*;
+use crate::ping::strip_formatting;
+use crate::server_handler::ServerHandler;
+use crate::*;
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const HISTORY_SIZE: usize = 60;
+const LAG_MARKER: &str = "Can't keep up!";
+//Lag warnings further apart than this don't belong to the same lag period
+const LAG_PERIOD_GAP: u64 = 60;
+const SAMPLES_PER_GAP: u64 = 2;
+const MAX_TPS: f64 = 20.0;
+
+//Command, line that identifies its answer and the other lines of the answer
+const TPS_PROBES: [(&str, &[&str]); 4] = [
+    ("tps", &["TPS from last"]),
+    ("forge tps", &["Mean TPS"]),
+    ("neoforge tps", &["Mean TPS"]),
+    ("tick query", &["The game is", "time per tick", "Target tick rate"]),
+];
+//Lines of every answer parse_tps understands. A custom tps_command has to answer like one of the above
+const TPS_ANSWER_MARKERS: [&str; 4] = ["TPS from last", "Mean TPS", "time per tick", "Target tick rate"];
+//Answers of vanilla and Bukkit to the commands they don't have, hidden while looking for the TPS command
+const UNKNOWN_COMMAND_MARKERS: [&str; 3] = ["Unknown or incomplete command", "<--[HERE]", "Unknown command"];
+
+//"Can't keep up! Is the server overloaded? Running 2035ms or 40 ticks behind"
+pub fn parse_lag_line(line: &str) -> Option<(u64, u64)> {
+    let rest = &line[line.find(LAG_MARKER)?..];
+    let ms = number_after(rest, "Running")? as u64;
+    let ticks = number_after(rest, "ms or")? as u64;
+    Some((ms, ticks))
+}
+
+//First number found after the marker, ignoring color codes
+fn number_after(text: &str, marker: &str) -> Option<f64> {
+    let start = text.find(marker)? + marker.len();
+    let rest = strip_formatting(&text[start..]);
+    let number: String = rest
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    number.trim_end_matches('.').parse().ok()
+}
+
+//Returns the TPS and, when the server tells it, the milliseconds per tick
+fn parse_tps(answer: &str) -> Option<(f64, Option<f64>)> {
+    if let Some(tps) = number_after(answer, "TPS from last 1m, 5m, 15m:") {
+        return Some((tps, None));
+    }
+    //Forge writes a line per dimension and another one for the whole server
+    let forge_line = answer
+        .lines()
+        .find(|l| l.contains("Overall"))
+        .unwrap_or(answer);
+    if let Some(tps) = number_after(forge_line, "Mean TPS:") {
+        return Some((tps, number_after(forge_line, "Mean tick time:")));
+    }
+    if let Some(mspt) = number_after(answer, "Average time per tick:") {
+        let target = number_after(answer, "Target tick rate:").unwrap_or(MAX_TPS);
+        let tps = if mspt > 0.0 {
+            (1000.0 / mspt).min(target)
+        } else {
+            target
+        };
+        return Some((tps, Some(mspt)));
+    }
+    None
+}
+
+struct Sample {
+    time: Instant,
+    tps: f64,
+    mspt: Option<f64>,
+}
+
+struct LagWarning {
+    time: Instant,
+    ms: u64,
+    ticks: u64,
+}
+
+enum Probe {
+    Unknown,
+    //Index in TPS_PROBES of the command being tried
+    Detecting(usize),
+    Command(String, Vec<&'static str>),
+    Unsupported,
+}
+
+/*
+    Keeps a short history of the TPS of the server and the
+    "Can't keep up!" warnings. Low TPS samples and lag warnings
+    start a lag period, and an alert is raised if it lasts.
+    The TPS command is run without waiting for its answer, which
+    comes back to the main loop as InputPacket::TpsAnswer
+*/
+pub struct PerformanceMonitor {
+    samples: VecDeque<Sample>,
+    lags: VecDeque<LagWarning>,
+    probe: Probe,
+    //A TPS command was sent and its answer hasn't come yet
+    waiting: bool,
+    threshold: f64,
+    alert_time: Duration,
+    period_gap: Duration,
+    lagging_since: Option<Instant>,
+    last_lag: Option<Instant>,
+    alerted: bool,
+}
+
+impl PerformanceMonitor {
+    pub fn new(config: &Config) -> PerformanceMonitor {
+        let probe = match &config.performance_tps_command {
+            Some(command) => {
+                let patterns = TPS_PROBES
+                    .iter()
+                    .find(|(c, _)| c == command)
+                    .map(|(_, p)| p.to_vec())
+                    .unwrap_or_else(|| TPS_ANSWER_MARKERS.to_vec());
+                Probe::Command(command.clone(), patterns)
+            }
+            None => Probe::Unknown,
+        };
+        PerformanceMonitor {
+            samples: VecDeque::with_capacity(HISTORY_SIZE),
+            lags: VecDeque::with_capacity(HISTORY_SIZE),
+            probe,
+            waiting: false,
+            threshold: config.performance_tps_threshold,
+            alert_time: Duration::from_secs(config.performance_alert_time),
+            period_gap: Duration::from_secs(
+                LAG_PERIOD_GAP.max(config.performance_interval.unwrap_or(0) * SAMPLES_PER_GAP),
+            ),
+            lagging_since: None,
+            last_lag: None,
+            alerted: false,
+        }
+    }
+
+    pub fn record_lag(&mut self, ms: u64, ticks: u64) {
+        if self.lags.len() == HISTORY_SIZE {
+            self.lags.pop_front();
+        }
+        self.lags.push_back(LagWarning {
+            time: Instant::now(),
+            ms,
+            ticks,
+        });
+        self.lagging();
+    }
+
+    //The TPS command is looked for once the server has started, trying one candidate after the other
+    pub fn sample(&mut self, server: &mut ServerHandler) {
+        if self.waiting || !server.is_ready() {
+            return;
+        }
+        if let Probe::Unknown = self.probe {
+            self.probe = Probe::Detecting(0);
+        }
+        match &self.probe {
+            Probe::Detecting(i) => self.ask(server, TPS_PROBES[*i].0, &probe_patterns(*i)),
+            Probe::Command(command, patterns) => {
+                let (command, patterns) = (command.clone(), patterns.clone());
+                self.ask(server, &command, &patterns);
+            }
+            _ => (),
+        }
+    }
+
+    fn ask(&mut self, server: &mut ServerHandler, command: &str, patterns: &[&str]) {
+        let input = get_input_sender();
+        self.waiting = server
+            .query_async(command, patterns, move |answer| {
+                let _ = input.send(InputPacket::TpsAnswer(answer.ok()));
+            })
+            .is_ok();
+    }
+
+    pub fn answer(&mut self, server: Option<&mut ServerHandler>, answer: Option<String>) {
+        let out = get_output_sender();
+        self.waiting = false;
+        let sample = answer.as_deref().and_then(parse_tps);
+        if let Probe::Detecting(i) = self.probe {
+            let (command, patterns) = TPS_PROBES[i];
+            match (sample, server) {
+                (Some(_), _) => {
+                    self.probe = Probe::Command(String::from(command), patterns.to_vec());
+                    infoln!(out, "Measuring the TPS of the server with \"{}\"", command);
+                }
+                //It stopped or restarted meanwhile, so the rest of the commands can't be trusted
+                (None, None) => self.probe = Probe::Unknown,
+                (None, Some(server)) if !server.is_ready() => self.probe = Probe::Unknown,
+                (None, Some(server)) if i + 1 < TPS_PROBES.len() => {
+                    self.probe = Probe::Detecting(i + 1);
+                    self.ask(server, TPS_PROBES[i + 1].0, &probe_patterns(i + 1));
+                }
+                (None, Some(_)) => {
+                    self.probe = Probe::Unsupported;
+                    warnln!(
+                        out,
+                        "The server doesn't tell its TPS. Only the \"Can't keep up!\" warnings will be watched"
+                    );
+                }
+            }
+        }
+
+        //The server could be starting or restarting
+        let (tps, mspt) = match sample {
+            Some(sample) => sample,
+            None => return,
+        };
+        if self.samples.len() == HISTORY_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            time: Instant::now(),
+            tps,
+            mspt,
+        });
+
+        if tps < self.threshold {
+            self.lagging();
+        } else if self.lagging_since.take().is_some() && self.alerted {
+            self.alerted = false;
+            infoln!(out, "The server has recovered. TPS: {:.1}", tps);
+        }
+    }
+
+    fn lagging(&mut self) {
+        let now = Instant::now();
+        let same_period = self
+            .last_lag
+            .map(|t| now.duration_since(t) < self.period_gap)
+            .unwrap_or(false);
+        if !same_period || self.lagging_since.is_none() {
+            self.lagging_since = Some(now);
+            self.alerted = false;
+        }
+        self.last_lag = Some(now);
+
+        let since = self.lagging_since.unwrap();
+        if !self.alerted && now.duration_since(since) >= self.alert_time {
+            self.alerted = true;
+            let out = get_output_sender();
+            errorln!(
+                out,
+                "The server has been lagging for {} minutes. {}",
+                now.duration_since(since).as_secs() / 60,
+                self.summary()
+            );
+        }
+    }
+
+    fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(last) = self.samples.back() {
+            parts.push(format!("TPS: {:.1}", last.tps));
+            if let Some(mspt) = last.mspt {
+                parts.push(format!("MSPT: {:.1}", mspt));
+            }
+        }
+        let recent: Vec<&LagWarning> = self
+            .lags
+            .iter()
+            .filter(|l| l.time.elapsed() < self.alert_time)
+            .collect();
+        if !recent.is_empty() {
+            parts.push(format!(
+                "{} lag warnings, up to {} ticks ({} ms) behind",
+                recent.len(),
+                recent.iter().map(|l| l.ticks).max().unwrap_or(0),
+                recent.iter().map(|l| l.ms).max().unwrap_or(0)
+            ));
+        }
+        parts.join(". ")
+    }
+
+    //perf: shows the recent performance of the server
+    pub fn report(&self) {
+        let out = get_output_sender();
+        if self.samples.is_empty() && self.lags.is_empty() {
+            infoln!(out, "There is no performance data yet");
+            return;
+        }
+        if !self.samples.is_empty() {
+            let tps: Vec<f64> = self.samples.iter().map(|s| s.tps).collect();
+            let average = tps.iter().sum::<f64>() / tps.len() as f64;
+            let minimum = tps.iter().cloned().fold(MAX_TPS, f64::min);
+            let last = self.samples.back().unwrap();
+            infoln!(
+                out,
+                "TPS: {:.1} now, {:.1} average and {:.1} minimum in the last {} minutes{}",
+                last.tps,
+                average,
+                minimum,
+                self.samples.front().unwrap().time.elapsed().as_secs() / 60,
+                last.mspt
+                    .map(|m| format!(". MSPT: {:.1}", m))
+                    .unwrap_or_default()
+            );
+        }
+        match self.lags.back() {
+            Some(last) => {
+                infoln!(
+                    out,
+                    "{} lag warnings. Last one {} seconds ago: {} ms ({} ticks) behind",
+                    self.lags.len(),
+                    last.time.elapsed().as_secs(),
+                    last.ms,
+                    last.ticks
+                );
+            }
+            None => {
+                infoln!(out, "No lag warnings");
+            }
+        }
+        if self.alerted {
+            warnln!(out, "The server is lagging");
+        }
+    }
+}
+
+//The answer of the candidate or the error of a server that doesn't have it
+fn probe_patterns(i: usize) -> Vec<&'static str> {
+    let mut patterns = TPS_PROBES[i].1.to_vec();
+    patterns.extend_from_slice(&UNKNOWN_COMMAND_MARKERS);
+    patterns
+}
+
+/********** Sampling ticker **********/
+
+//Asks the main loop to take a sample, as it owns the server
+pub struct PerformanceTicker {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl PerformanceTicker {
+    pub fn start(interval: u64) -> PerformanceTicker {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_c = stop.clone();
+        let input = get_input_sender();
+        let handle = thread::spawn(move || {
+            loop {
+                thread::park_timeout(Duration::from_secs(interval));
+                if stop_c.load(Ordering::SeqCst) {
+                    break;
+                }
+                if input.send(InputPacket::PerformanceSample).is_err() {
+                    break;
+                }
+            }
+        });
+        PerformanceTicker { stop, handle }
+    }
+
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.thread().unpark();
+        self.handle.join().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_lag_warnings() {
+        let line = "[12:00:00] [Server thread/WARN]: Can't keep up! Is the server overloaded? Running 2035ms or 40 ticks behind";
+        assert_eq!(parse_lag_line(line), Some((2035, 40)));
+        assert_eq!(parse_lag_line("[12:00:00] [Server thread/INFO]: Running 2035ms or 40 ticks behind"), None);
+    }
+
+    #[test]
+    fn reads_the_tps_answers() {
+        assert_eq!(
+            parse_tps("§6TPS from last 1m, 5m, 15m: §a19.98, §a*20.0, §a20.0"),
+            Some((19.98, None))
+        );
+        let forge = "Dim minecraft:overworld (minecraft:overworld): Mean tick time: 3.2 ms. Mean TPS: 20.000\n\
+                     Overall: Mean tick time: 50.5 ms. Mean TPS: 19.802";
+        assert_eq!(parse_tps(forge), Some((19.802, Some(50.5))));
+        let vanilla = "The game is running normally\nTarget tick rate: 20.0 per second.\nAverage time per tick: 62.5ms (Target: 50.0ms)";
+        assert_eq!(parse_tps(vanilla), Some((16.0, Some(62.5))));
+        let fast = "Target tick rate: 10.0 per second.\nAverage time per tick: 12.5ms (Target: 100.0ms)";
+        assert_eq!(parse_tps(fast), Some((10.0, Some(12.5))));
+        assert_eq!(parse_tps("Unknown or incomplete command, see below for error"), None);
+    }
+
+    #[test]
+    fn probes_also_wait_for_unknown_commands() {
+        for i in 0..TPS_PROBES.len() {
+            let patterns = probe_patterns(i);
+            assert!(patterns.contains(&TPS_PROBES[i].1[0]));
+            assert!(UNKNOWN_COMMAND_MARKERS.iter().all(|m| patterns.contains(m)));
+        }
+    }
+}
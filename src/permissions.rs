@@ -0,0 +1,210 @@
+use crate::io::*;
+use crate::*;
+
+use regex::Regex;
+use std::collections::HashMap;
+
+/*
+    Telegram users other than the one in user_id get a role. The rules of a
+    role (or of a user id) decide which commands they can send: a command
+    matching a deny rule is rejected and, if there are allow rules, it has
+    to match one of them. Without rules every command is allowed
+*/
+
+#[derive(Clone)]
+enum Rule {
+    //The first words of the command
+    Prefix(String),
+    Pattern(Regex),
+}
+
+impl Rule {
+    fn matches(&self, command: &str) -> bool {
+        match self {
+            Rule::Prefix(prefix) => {
+                command == prefix
+                    || command
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with(char::is_whitespace))
+            }
+            Rule::Pattern(pattern) => pattern.is_match(command),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CommandRules {
+    allow: Vec<Rule>,
+    deny: Vec<Rule>,
+}
+
+impl CommandRules {
+    //allow, deny, allow_regex or deny_regex
+    pub fn add(&mut self, key: &str, val: &str) -> GenericResult<()> {
+        let (list, rule) = match key {
+            "allow" => (&mut self.allow, Rule::Prefix(normalize(val))),
+            "deny" => (&mut self.deny, Rule::Prefix(normalize(val))),
+            "allow_regex" | "deny_regex" => {
+                let pattern =
+                    Regex::new(val).map_err(|e| format!("Invalid command rule {}: {}", val, e))?;
+                match key {
+                    "allow_regex" => (&mut self.allow, Rule::Pattern(pattern)),
+                    _ => (&mut self.deny, Rule::Pattern(pattern)),
+                }
+            }
+            _ => return Err(format!("Unknown command rule: {}", key).into()),
+        };
+        list.push(rule);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Permissions {
+    roles: HashMap<i64, String>,
+    rules: HashMap<String, CommandRules>,
+}
+
+impl Permissions {
+    pub fn set_role(&mut self, user_id: i64, role: &str) {
+        self.roles.insert(user_id, String::from(role));
+    }
+
+    //Rules of a role or a user id
+    pub fn rules_mut(&mut self, name: &str) -> &mut CommandRules {
+        self.rules.entry(String::from(name)).or_default()
+    }
+
+    pub fn has_user(&self, user_id: i64) -> bool {
+        self.roles.contains_key(&user_id)
+    }
+
+    /*
+        Every line is checked, as the server runs each one as a command.
+        "@name" is left out so the rules are the same for every server
+    */
+    pub fn check(&self, user_id: i64, message: &str) -> GenericResult<()> {
+        let rules: Vec<&CommandRules> =
+            [Some(user_id.to_string()), self.roles.get(&user_id).cloned()]
+                .iter()
+                .flatten()
+                .filter_map(|name| self.rules.get(name))
+                .collect();
+        for line in message.lines() {
+            let command = normalize(line);
+            if command.is_empty() {
+                continue;
+            }
+            for r in &rules {
+                if r.deny.iter().any(|rule| rule.matches(&command))
+                    || (!r.allow.is_empty() && !r.allow.iter().any(|rule| rule.matches(&command)))
+                {
+                    return Err(
+                        format!("You are not allowed to use the command {}", command).into(),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*
+        Console commands run for a user by a manager command ("op Steve" for
+        "ops add Steve") follow the rules too, so they can't be got around.
+        Denied commands are reported with the manager command that ran them
+    */
+    pub fn allows(&self, user: Option<i64>, command: &str, by: &str) -> bool {
+        match user.map(|u| (u, self.check(u, command))) {
+            Some((user, Err(e))) => {
+                report_denied(user, &format!("{} ({})", e, by).into());
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+//Logged and told to the user
+pub fn report_denied(user_id: i64, error: &GenericError) {
+    let out = get_output_sender();
+    warnln!(out, "Command of the Telegram user {} denied: {}", user_id, error);
+    let _ = out.send(OutputPacket::Reply {
+        user: user_id,
+        message: error.to_string(),
+    });
+}
+
+//"@survival /op Steve" -> "op Steve"
+fn normalize(command: &str) -> String {
+    let mut command = command.trim();
+    if let Some(address) = command.strip_prefix('@') {
+        command = address
+            .split_once(char::is_whitespace)
+            .map(|(_, rest)| rest.trim_start())
+            .unwrap_or("");
+    }
+    command.trim_start_matches('/').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[(&str, &str)]) -> CommandRules {
+        let mut command_rules = CommandRules::default();
+        for (key, val) in rules {
+            command_rules.add(key, val).unwrap();
+        }
+        command_rules
+    }
+
+    #[test]
+    fn normalizes_commands() {
+        assert_eq!(normalize("  /op Steve "), "op Steve");
+        assert_eq!(normalize("@survival /op Steve"), "op Steve");
+        assert_eq!(normalize("@survival"), "");
+        assert_eq!(normalize("say hi"), "say hi");
+    }
+
+    #[test]
+    fn prefixes_match_whole_words() {
+        let rule = Rule::Prefix(normalize("/whitelist add"));
+        assert!(rule.matches("whitelist add"));
+        assert!(rule.matches("whitelist add Steve"));
+        assert!(!rule.matches("whitelist addition"));
+        assert!(!rule.matches("whitelist remove Steve"));
+        assert!(CommandRules::default().add("allow_regex", "(").is_err());
+        assert!(CommandRules::default().add("allow_all", "say").is_err());
+    }
+
+    #[test]
+    fn deny_rules_win_over_allow_rules() {
+        let mut permissions = Permissions::default();
+        permissions.set_role(1, "moderator");
+        *permissions.rules_mut("moderator") = rules(&[("allow", "whitelist"), ("allow", "say"), ("deny", "whitelist remove")]);
+        assert!(permissions.check(1, "whitelist add Steve").is_ok());
+        assert!(permissions.check(1, "/say hi").is_ok());
+        assert!(permissions.check(1, "whitelist remove Steve").is_err());
+        assert!(permissions.check(1, "op Steve").is_err());
+        //Every line is a command
+        assert!(permissions.check(1, "say hi\n\nop Steve").is_err());
+        assert!(permissions.check(1, "say hi\n@survival say bye").is_ok());
+    }
+
+    #[test]
+    fn user_and_role_rules_both_apply() {
+        let mut permissions = Permissions::default();
+        permissions.set_role(1, "player");
+        permissions.set_role(2, "player");
+        *permissions.rules_mut("player") = rules(&[("deny_regex", "^(op|deop) ")]);
+        *permissions.rules_mut("1") = rules(&[("allow", "say"), ("allow", "op")]);
+        assert!(permissions.check(1, "say hi").is_ok());
+        assert!(permissions.check(1, "op Steve").is_err());
+        assert!(permissions.check(1, "list").is_err());
+        assert!(permissions.check(2, "list").is_ok());
+        assert!(permissions.check(2, "deop Steve").is_err());
+        //Without rules every command is allowed
+        assert!(permissions.check(3, "op Steve").is_ok());
+        assert!(permissions.allows(None, "op Steve", "test"));
+    }
+}
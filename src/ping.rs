@@ -0,0 +1,310 @@
+use crate::*;
+
+use serde_json::Value;
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+
+/*
+    Server List Ping protocol, the one used by the clients to fill the
+    multiplayer server list. See https://wiki.vg/Server_List_Ping
+*/
+
+const DEFAULT_PORT: u16 = 25565;
+const STATUS_STATE: i32 = 1;
+const TIMEOUT: u64 = 5;
+const READY_POLLING_TIME: u64 = 2;
+const MAX_PACKET_LENGTH: i32 = 1 << 21;
+
+pub struct ServerStatus {
+    pub motd: String,
+    pub version: String,
+    pub protocol: i64,
+    pub online_players: i64,
+    pub max_players: i64,
+    pub players_sample: Vec<String>,
+    pub latency: Duration,
+}
+
+pub fn ping(address: &str) -> GenericResult<ServerStatus> {
+    let (host, port) = split_address(address)?;
+    let socket_addr = (host.as_str(), port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| GenericError::from(format!("Could not resolve {}", host)))?;
+    let mut stream = TcpStream::connect_timeout(&socket_addr, Duration::from_secs(TIMEOUT))?;
+    stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(TIMEOUT)))?;
+
+    let mut handshake = Vec::new();
+    //Any protocol version is accepted when asking for the status
+    write_varint(&mut handshake, -1);
+    write_string(&mut handshake, &host);
+    handshake.extend_from_slice(&port.to_be_bytes());
+    write_varint(&mut handshake, STATUS_STATE);
+    write_packet(&mut stream, 0x00, &handshake)?;
+    write_packet(&mut stream, 0x00, &[])?;
+
+    let (id, data) = read_packet(&mut stream)?;
+    if id != 0x00 {
+        return Err(format!("Unexpected status response packet {}", id).into());
+    }
+    let json = read_string(&mut data.as_slice())?;
+    let json: Value = serde_json::from_str(&json)
+        .map_err(|e| GenericError::from(format!("Invalid status response: {}", e)))?;
+
+    let payload = chrono::Local::now().timestamp_millis();
+    let start = Instant::now();
+    write_packet(&mut stream, 0x01, &payload.to_be_bytes())?;
+    let (id, _) = read_packet(&mut stream)?;
+    if id != 0x01 {
+        return Err(format!("Unexpected pong packet {}", id).into());
+    }
+    let latency = start.elapsed();
+
+    let players = &json["players"];
+    Ok(ServerStatus {
+        motd: strip_formatting(&chat_to_text(&json["description"])),
+        version: json["version"]["name"].as_str().unwrap_or("").to_string(),
+        protocol: json["version"]["protocol"].as_i64().unwrap_or(-1),
+        online_players: players["online"].as_i64().unwrap_or(0),
+        max_players: players["max"].as_i64().unwrap_or(0),
+        players_sample: players["sample"]
+            .as_array()
+            .map(|sample| {
+                sample
+                    .iter()
+                    .filter_map(|p| p["name"].as_str())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        latency,
+    })
+}
+
+//The server is ready once it answers to the clients, not when the process is alive
+pub fn wait_until_ready(address: &str, timeout: u64) -> GenericResult<ServerStatus> {
+    let start = Instant::now();
+    loop {
+        match ping(address) {
+            Ok(status) => return Ok(status),
+            Err(e) => {
+                if start.elapsed() >= Duration::from_secs(timeout) {
+                    return Err(format!(
+                        "Server is not accepting connections after {} seconds: {}",
+                        timeout, e
+                    )
+                    .into());
+                }
+            }
+        }
+        thread::sleep(Duration::from_secs(READY_POLLING_TIME));
+    }
+}
+
+pub fn split_address(address: &str) -> GenericResult<(String, u16)> {
+    match address.rfind(':') {
+        Some(i) => {
+            let port = address[i + 1..]
+                .parse::<u16>()
+                .map_err(|_| GenericError::from(format!("Invalid port in {}", address)))?;
+            Ok((String::from(&address[..i]), port))
+        }
+        None => Ok((String::from(address), DEFAULT_PORT)),
+    }
+}
+
+//Descriptions can be plain strings or chat components with nested "extra" components
+pub fn chat_to_text(component: &Value) -> String {
+    match component {
+        Value::String(s) => s.clone(),
+        Value::Array(parts) => parts.iter().map(chat_to_text).collect(),
+        Value::Object(obj) => {
+            let mut text = obj
+                .get("text")
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .to_string();
+            if let Some(extra) = obj.get("extra") {
+                text.push_str(&chat_to_text(extra));
+            }
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+pub fn strip_formatting(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/********** Protocol primitives **********/
+
+pub(crate) fn write_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7F == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+pub(crate) fn read_varint<R: Read>(reader: &mut R) -> GenericResult<i32> {
+    let mut result: u32 = 0;
+    for i in 0..5 {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        result |= ((byte[0] & 0x7F) as u32) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(result as i32);
+        }
+    }
+    Err("VarInt is too big".into())
+}
+
+pub(crate) fn write_string(buf: &mut Vec<u8>, s: &str) {
+    write_varint(buf, s.len() as i32);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+pub(crate) fn read_string<R: Read>(reader: &mut R) -> GenericResult<String> {
+    let length = read_varint(reader)?;
+    if !(0..MAX_PACKET_LENGTH).contains(&length) {
+        return Err(format!("Invalid string length {}", length).into());
+    }
+    let mut buf = vec![0u8; length as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+pub(crate) fn write_packet<W: Write>(writer: &mut W, id: i32, data: &[u8]) -> GenericResult<()> {
+    let mut body = Vec::with_capacity(data.len() + 5);
+    write_varint(&mut body, id);
+    body.extend_from_slice(data);
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    write_varint(&mut packet, body.len() as i32);
+    packet.extend_from_slice(&body);
+    writer.write_all(&packet)?;
+    Ok(())
+}
+
+pub(crate) fn read_packet<R: Read>(reader: &mut R) -> GenericResult<(i32, Vec<u8>)> {
+    let length = read_varint(reader)?;
+    if !(1..MAX_PACKET_LENGTH).contains(&length) {
+        return Err(format!("Invalid packet length {}", length).into());
+    }
+    let mut buf = vec![0u8; length as usize];
+    reader.read_exact(&mut buf)?;
+    let mut data = buf.as_slice();
+    let id = read_varint(&mut data)?;
+    Ok((id, Vec::from(data)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::net::TcpListener;
+
+    #[test]
+    fn varints_round_trip() {
+        let cases: [(i32, &[u8]); 6] = [
+            (0, &[0x00]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (25565, &[0xdd, 0xc7, 0x01]),
+            (i32::MAX, &[0xff, 0xff, 0xff, 0xff, 0x07]),
+            (-1, &[0xff, 0xff, 0xff, 0xff, 0x0f]),
+        ];
+        for (value, bytes) in &cases {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, *value);
+            assert_eq!(buf, *bytes);
+            assert_eq!(read_varint(&mut &bytes[..]).unwrap(), *value);
+        }
+        assert!(read_varint(&mut &[0x80u8, 0x80, 0x80, 0x80, 0x80, 0x01][..]).is_err());
+        assert!(read_varint(&mut &[0x80u8][..]).is_err());
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let mut data = Vec::new();
+        write_string(&mut data, "§aHello");
+        let mut packet = Vec::new();
+        write_packet(&mut packet, 0x00, &data).unwrap();
+        let (id, data) = read_packet(&mut packet.as_slice()).unwrap();
+        assert_eq!(id, 0x00);
+        assert_eq!(read_string(&mut data.as_slice()).unwrap(), "§aHello");
+
+        let mut packet = Vec::new();
+        write_varint(&mut packet, MAX_PACKET_LENGTH);
+        assert!(read_packet(&mut packet.as_slice()).is_err());
+        assert!(read_packet(&mut &[0u8][..]).is_err());
+        assert!(read_string(&mut &[0x05u8, b'a'][..]).is_err());
+    }
+
+    #[test]
+    fn reads_addresses_and_descriptions() {
+        assert_eq!(split_address("localhost").unwrap(), (String::from("localhost"), DEFAULT_PORT));
+        assert_eq!(split_address("127.0.0.1:25566").unwrap(), (String::from("127.0.0.1"), 25566));
+        assert!(split_address("localhost:port").is_err());
+
+        let description = json!({"text": "§lA ", "extra": [{"text": "Minecraft"}, " Server"]});
+        assert_eq!(chat_to_text(&description), "§lA Minecraft Server");
+        assert_eq!(strip_formatting("§lA §cMinecraft Server§"), "A Minecraft Server");
+    }
+
+    //A server that answers one status request
+    fn fake_server(status: Value) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (id, data) = read_packet(&mut stream).unwrap();
+            let mut data = data.as_slice();
+            assert_eq!(id, 0x00);
+            assert_eq!(read_varint(&mut data).unwrap(), -1);
+            assert_eq!(read_string(&mut data).unwrap(), "127.0.0.1");
+            assert_eq!(read_packet(&mut stream).unwrap(), (0x00, Vec::new()));
+
+            let mut response = Vec::new();
+            write_string(&mut response, &status.to_string());
+            write_packet(&mut stream, 0x00, &response).unwrap();
+            let (id, payload) = read_packet(&mut stream).unwrap();
+            assert_eq!(id, 0x01);
+            write_packet(&mut stream, 0x01, &payload).unwrap();
+        });
+        address
+    }
+
+    #[test]
+    fn pings_a_server() {
+        let address = fake_server(json!({
+            "version": {"name": "1.20.4", "protocol": 765},
+            "players": {"max": 20, "online": 2, "sample": [{"name": "Steve", "id": ""}, {"name": "Alex", "id": ""}]},
+            "description": {"text": "§aA Minecraft Server"}
+        }));
+        let status = ping(&address).unwrap();
+        assert_eq!(status.motd, "A Minecraft Server");
+        assert_eq!(status.version, "1.20.4");
+        assert_eq!(status.protocol, 765);
+        assert_eq!((status.online_players, status.max_players), (2, 20));
+        assert_eq!(status.players_sample, vec!["Steve", "Alex"]);
+    }
+}
@@ -0,0 +1,348 @@
+use crate::backup::compute_hash;
+use crate::io::*;
+use crate::*;
+
+use serde_json::{json, Map, Value};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+use zip::ZipArchive;
+
+const DISABLED_DIRECTORY: &str = "disabled";
+//Contents of the folder when the server was last started
+const SNAPSHOT_FILE: &str = ".last_start.json";
+const JAR_EXTENSION: &str = "jar";
+const UNKNOWN_VERSION: &str = "unknown version";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AddonFolder {
+    Plugins,
+    Mods,
+}
+
+impl AddonFolder {
+    pub fn from_command(command: &str) -> Option<AddonFolder> {
+        match command {
+            "plugins" => Some(AddonFolder::Plugins),
+            "mods" => Some(AddonFolder::Mods),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            AddonFolder::Plugins => "plugins",
+            AddonFolder::Mods => "mods",
+        }
+    }
+
+    fn path(self, config: &Config) -> PathBuf {
+        config.server_path().join(self.name())
+    }
+}
+
+pub struct Addon {
+    pub file: PathBuf,
+    pub name: String,
+    pub version: Option<String>,
+    pub hash: String,
+}
+
+impl Addon {
+    pub fn load(file: &Path) -> GenericResult<Addon> {
+        let hash = hex::encode(compute_hash(file)?);
+        let (name, version) = read_metadata(file).unwrap_or((None, None));
+        let name = name.unwrap_or_else(|| {
+            file.file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        Ok(Addon {
+            file: PathBuf::from(file),
+            name,
+            version,
+            hash,
+        })
+    }
+
+    fn file_name(&self) -> String {
+        self.file
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/********** Jar metadata **********/
+
+//Name and version from plugin.yml, fabric.mod.json or mods.toml
+fn read_metadata(jar: &Path) -> GenericResult<(Option<String>, Option<String>)> {
+    let mut archive = ZipArchive::new(File::open(jar)?)
+        .map_err(|e| GenericError::from(format!("{}", e)))?;
+
+    for file in &["plugin.yml", "paper-plugin.yml", "bungee.yml"] {
+        if let Some(content) = read_entry(&mut archive, file) {
+            return Ok((yaml_value(&content, "name"), yaml_value(&content, "version")));
+        }
+    }
+    if let Some(content) = read_entry(&mut archive, "fabric.mod.json") {
+        let json: Value = serde_json::from_str(&content)
+            .map_err(|e| GenericError::from(format!("Invalid fabric.mod.json: {}", e)))?;
+        let name = json["name"].as_str().or_else(|| json["id"].as_str());
+        return Ok((
+            name.map(String::from),
+            json["version"].as_str().map(String::from),
+        ));
+    }
+    for file in &["META-INF/mods.toml", "META-INF/neoforge.mods.toml"] {
+        if let Some(content) = read_entry(&mut archive, file) {
+            let name = toml_value(&content, "displayName").or_else(|| toml_value(&content, "modId"));
+            //Forge replaces this placeholder with the version in the manifest
+            let version = match toml_value(&content, "version") {
+                Some(v) if v.contains("${") => read_entry(&mut archive, "META-INF/MANIFEST.MF")
+                    .and_then(|m| manifest_value(&m, "Implementation-Version")),
+                v => v,
+            };
+            return Ok((name, version));
+        }
+    }
+    Ok((None, None))
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Option<String> {
+    let mut entry = archive.by_name(name).ok()?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content).ok()?;
+    Some(content)
+}
+
+fn unquote(value: &str) -> String {
+    value.trim().trim_matches(|c| c == '"' || c == '\'').to_string()
+}
+
+//Top level keys only
+fn yaml_value(content: &str, key: &str) -> Option<String> {
+    content
+        .lines()
+        .filter(|l| !l.starts_with(char::is_whitespace))
+        .find_map(|l| {
+            let (k, v) = l.split_once(':')?;
+            if k.trim() == key {
+                Some(unquote(v))
+            } else {
+                None
+            }
+        })
+}
+
+//First value of the key after [[mods]]
+fn toml_value(content: &str, key: &str) -> Option<String> {
+    content
+        .lines()
+        .skip_while(|l| l.trim() != "[[mods]]")
+        .find_map(|l| {
+            let (k, v) = l.split_once('=')?;
+            if k.trim() == key {
+                //Comments after the value
+                let v = v.trim();
+                let v = match v.strip_prefix('"') {
+                    Some(rest) => rest.split('"').next().unwrap_or(""),
+                    None => v.split('#').next().unwrap_or(""),
+                };
+                Some(unquote(v))
+            } else {
+                None
+            }
+        })
+}
+
+fn manifest_value(content: &str, key: &str) -> Option<String> {
+    content.lines().find_map(|l| {
+        let (k, v) = l.split_once(':')?;
+        if k.trim() == key {
+            Some(v.trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/********** Folder contents **********/
+
+fn list_jars(directory: &Path) -> Vec<PathBuf> {
+    let mut jars: Vec<PathBuf> = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().map(|e| e == JAR_EXTENSION).unwrap_or(false))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    jars.sort();
+    jars
+}
+
+fn load_addons(directory: &Path) -> Vec<Addon> {
+    let out = get_output_sender();
+    list_jars(directory)
+        .iter()
+        .filter_map(|jar| match Addon::load(jar) {
+            Ok(addon) => Some(addon),
+            Err(e) => {
+                warnln!(out, "Could not read {}: {}", jar.display(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+//Finds a jar by the name of the addon or by its file name
+fn find_addon(directory: &Path, name: &str) -> Option<Addon> {
+    load_addons(directory).into_iter().find(|a| {
+        a.name.eq_ignore_ascii_case(name)
+            || a.file_name().eq_ignore_ascii_case(name)
+            || a.file
+                .file_stem()
+                .map(|s| s.to_string_lossy().eq_ignore_ascii_case(name))
+                .unwrap_or(false)
+    })
+}
+
+fn describe(addon: &Addon) -> String {
+    format!(
+        "{} {} ({}) {}",
+        addon.name,
+        addon.version.as_deref().unwrap_or(UNKNOWN_VERSION),
+        addon.file_name(),
+        addon.hash
+    )
+}
+
+/********** Changes since the last start **********/
+
+//Called every time the server starts
+pub fn save_snapshots(config: &Config) {
+    for folder in &[AddonFolder::Plugins, AddonFolder::Mods] {
+        let directory = folder.path(config);
+        if !directory.is_dir() {
+            continue;
+        }
+        let snapshot: Map<String, Value> = load_addons(&directory)
+            .into_iter()
+            .map(|a| {
+                (
+                    a.file_name(),
+                    json!({ "name": a.name, "version": a.version, "sha256": a.hash }),
+                )
+            })
+            .collect();
+        let content = Value::Object(snapshot).to_string();
+        if let Err(e) = fs::write(directory.join(SNAPSHOT_FILE), content) {
+            let out = get_output_sender();
+            warnln!(out, "Could not save the contents of {}: {}", folder.name(), e);
+        }
+    }
+}
+
+fn changes(directory: &Path) -> GenericResult<Vec<String>> {
+    let content = fs::read_to_string(directory.join(SNAPSHOT_FILE))
+        .map_err(|_| GenericError::from("The server hasn't been started by the manager yet"))?;
+    let snapshot: Map<String, Value> = serde_json::from_str(&content)
+        .map_err(|e| GenericError::from(format!("Invalid {}: {}", SNAPSHOT_FILE, e)))?;
+    let current = load_addons(directory);
+
+    let mut changes = Vec::new();
+    for addon in &current {
+        match snapshot.get(&addon.file_name()) {
+            None => changes.push(format!("Added: {}", describe(addon))),
+            Some(old) if old["sha256"].as_str() != Some(addon.hash.as_str()) => {
+                changes.push(format!(
+                    "Changed: {} (was {})",
+                    describe(addon),
+                    old["version"].as_str().unwrap_or(UNKNOWN_VERSION)
+                ))
+            }
+            Some(_) => (),
+        }
+    }
+    for (file, old) in snapshot.iter() {
+        if !current.iter().any(|a| a.file_name() == *file) {
+            changes.push(format!(
+                "Removed: {} {} ({})",
+                old["name"].as_str().unwrap_or(""),
+                old["version"].as_str().unwrap_or(UNKNOWN_VERSION),
+                file
+            ));
+        }
+    }
+    Ok(changes)
+}
+
+/********** Commands **********/
+
+/*
+    <folder> list: shows the jars with their version and hash
+    <folder> disable <name>: moves the jar to the disabled folder
+    <folder> enable <name>: moves it back
+    <folder> changes: shows what changed since the server was last started
+
+    Changes take effect the next time the server starts
+*/
+pub fn addons_command(folder: AddonFolder, args: &[&str], config: &Config) {
+    let out = get_output_sender();
+    let directory = folder.path(config);
+    let disabled = directory.join(DISABLED_DIRECTORY);
+
+    let result: GenericResult<()> = match args {
+        [] | ["list"] => {
+            let addons = load_addons(&directory);
+            infoln!(out, "{} ({}):", folder.name(), addons.len());
+            for addon in &addons {
+                infoln!(out, "{}", describe(addon));
+            }
+            let disabled = load_addons(&disabled);
+            if !disabled.is_empty() {
+                let names: Vec<&str> = disabled.iter().map(|a| a.name.as_str()).collect();
+                infoln!(out, "Disabled: {}", names.join(", "));
+            }
+            Ok(())
+        }
+        ["disable", name] => move_addon(&directory, &disabled, name).map(|file| {
+            infoln!(out, "{} disabled. Restart the server to apply it", file);
+        }),
+        ["enable", name] => move_addon(&disabled, &directory, name).map(|file| {
+            infoln!(out, "{} enabled. Restart the server to apply it", file);
+        }),
+        ["changes"] => changes(&directory).map(|changes| {
+            if changes.is_empty() {
+                infoln!(out, "No changes in {} since the last start", folder.name());
+            }
+            for change in changes {
+                infoln!(out, "{}", change);
+            }
+        }),
+        _ => {
+            warnln!(
+                out,
+                "Usage: {} [list | changes | enable <name> | disable <name>]",
+                folder.name()
+            );
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        errorln!(out, "{}", e);
+    }
+}
+
+fn move_addon(from: &Path, to: &Path, name: &str) -> GenericResult<String> {
+    let addon = find_addon(from, name)
+        .ok_or_else(|| GenericError::from(format!("There is no {} in {}", name, from.display())))?;
+    let file = addon.file_name();
+    fs::create_dir_all(to)?;
+    fs::rename(&addon.file, to.join(&file))?;
+    Ok(file)
+}
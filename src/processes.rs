@@ -155,6 +155,10 @@ impl ProcessHandler {
       Ok(serv)
    }
 
+   pub fn pid(&self) -> pid_t {
+      self.proc_pid
+   }
+
    pub fn is_dead(&mut self) -> bool {
       if self.dead.load(Ordering::SeqCst) {
          return true;
@@ -0,0 +1,496 @@
+use crate::io::*;
+use crate::server_handler::ServerHandler;
+use crate::*;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const PROPERTIES_FILE: &str = "server.properties";
+const DEFAULT_LEVEL_NAME: &str = "world";
+
+/*
+    server.properties is a Java properties file. Lines that are not
+    modified are written back as they were read, so comments, ordering
+    and the escaping made by the server are preserved
+*/
+
+enum Line {
+    Raw(String),
+    Entry {
+        key: String,
+        value: String,
+        text: String,
+    },
+}
+
+pub struct ServerProperties {
+    path: PathBuf,
+    lines: Vec<Line>,
+}
+
+enum Kind {
+    Bool,
+    Int(i64, i64),
+    Choice(&'static [&'static str]),
+    Text,
+}
+
+const DIFFICULTIES: &[&str] = &["peaceful", "easy", "normal", "hard"];
+const GAMEMODES: &[&str] = &["survival", "creative", "adventure", "spectator"];
+
+const KNOWN_PROPERTIES: &[(&str, Kind)] = &[
+    ("allow-flight", Kind::Bool),
+    ("allow-nether", Kind::Bool),
+    ("broadcast-console-to-ops", Kind::Bool),
+    ("broadcast-rcon-to-ops", Kind::Bool),
+    ("difficulty", Kind::Choice(DIFFICULTIES)),
+    ("enable-command-block", Kind::Bool),
+    ("enable-jmx-monitoring", Kind::Bool),
+    ("enable-query", Kind::Bool),
+    ("enable-rcon", Kind::Bool),
+    ("enable-status", Kind::Bool),
+    ("enforce-secure-profile", Kind::Bool),
+    ("enforce-whitelist", Kind::Bool),
+    ("entity-broadcast-range-percentage", Kind::Int(10, 1000)),
+    ("force-gamemode", Kind::Bool),
+    ("function-permission-level", Kind::Int(1, 4)),
+    ("gamemode", Kind::Choice(GAMEMODES)),
+    ("generate-structures", Kind::Bool),
+    ("generator-settings", Kind::Text),
+    ("hardcore", Kind::Bool),
+    ("hide-online-players", Kind::Bool),
+    ("initial-disabled-packs", Kind::Text),
+    ("initial-enabled-packs", Kind::Text),
+    ("level-name", Kind::Text),
+    ("level-seed", Kind::Text),
+    ("level-type", Kind::Text),
+    ("log-ips", Kind::Bool),
+    ("max-chained-neighbor-updates", Kind::Int(-1, i32::MAX as i64)),
+    ("max-players", Kind::Int(0, i32::MAX as i64)),
+    ("max-tick-time", Kind::Int(-1, i64::MAX)),
+    ("max-world-size", Kind::Int(1, 29_999_984)),
+    ("motd", Kind::Text),
+    ("network-compression-threshold", Kind::Int(-1, i32::MAX as i64)),
+    ("online-mode", Kind::Bool),
+    ("op-permission-level", Kind::Int(0, 4)),
+    ("player-idle-timeout", Kind::Int(0, i32::MAX as i64)),
+    ("prevent-proxy-connections", Kind::Bool),
+    ("pvp", Kind::Bool),
+    ("query.port", Kind::Int(1, 65535)),
+    ("rate-limit", Kind::Int(0, i32::MAX as i64)),
+    ("rcon.password", Kind::Text),
+    ("rcon.port", Kind::Int(1, 65535)),
+    ("require-resource-pack", Kind::Bool),
+    ("resource-pack", Kind::Text),
+    ("resource-pack-prompt", Kind::Text),
+    ("resource-pack-sha1", Kind::Text),
+    ("server-ip", Kind::Text),
+    ("server-port", Kind::Int(1, 65535)),
+    ("simulation-distance", Kind::Int(3, 32)),
+    ("spawn-animals", Kind::Bool),
+    ("spawn-monsters", Kind::Bool),
+    ("spawn-npcs", Kind::Bool),
+    ("spawn-protection", Kind::Int(0, i32::MAX as i64)),
+    ("sync-chunk-writes", Kind::Bool),
+    ("use-native-transport", Kind::Bool),
+    ("view-distance", Kind::Int(3, 32)),
+    ("white-list", Kind::Bool),
+];
+
+impl ServerProperties {
+    pub fn load(server_directory: &Path) -> GenericResult<Self> {
+        let path = server_directory.join(PROPERTIES_FILE);
+        let content = fs::read_to_string(&path)?;
+        let lines = content.lines().map(parse_line).collect();
+        Ok(Self { path, lines })
+    }
+
+    pub fn save(&self) -> GenericResult<()> {
+        let mut content = String::new();
+        for line in &self.lines {
+            match line {
+                Line::Raw(text) | Line::Entry { text, .. } => content.push_str(text),
+            }
+            content.push('\n');
+        }
+        fs::write(&self.path, content)?;
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        let text = format!("{}={}", escape(key, true), escape(value, false));
+        for line in self.lines.iter_mut() {
+            if let Line::Entry { key: k, .. } = line {
+                if k == key {
+                    *line = Line::Entry {
+                        key: String::from(key),
+                        value: String::from(value),
+                        text,
+                    };
+                    return;
+                }
+            }
+        }
+        self.lines.push(Line::Entry {
+            key: String::from(key),
+            value: String::from(value),
+            text,
+        });
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.lines
+            .retain(|l| !matches!(l, Line::Entry { key: k, .. } if k == key));
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(|l| match l {
+            Line::Entry { key, value, .. } => Some((key.as_str(), value.as_str())),
+            Line::Raw(_) => None,
+        })
+    }
+
+    //Keys whose value is different in other, with their value here and there
+    pub fn diff<'a>(&'a self, other: &'a Self) -> Vec<(&'a str, Option<&'a str>, Option<&'a str>)> {
+        let mut changes: Vec<(&str, Option<&str>, Option<&str>)> = self
+            .entries()
+            .filter(|(k, v)| other.get(k) != Some(*v))
+            .map(|(k, v)| (k, Some(v), other.get(k)))
+            .collect();
+        changes.extend(
+            other
+                .entries()
+                .filter(|(k, _)| self.get(k).is_none())
+                .map(|(k, v)| (k, None, Some(v))),
+        );
+        changes
+    }
+}
+
+//Directory of the world set in level-name
+pub fn world_path(config: &Config) -> PathBuf {
+    let server_path = config.server_path();
+    let level_name = ServerProperties::load(&server_path)
+        .ok()
+        .and_then(|p| p.get("level-name").map(String::from))
+        .unwrap_or_else(|| String::from(DEFAULT_LEVEL_NAME));
+    server_path.join(level_name)
+}
+
+pub fn validate(properties: &ServerProperties, key: &str, value: &str) -> GenericResult<()> {
+    let kind = match KNOWN_PROPERTIES.iter().find(|(k, _)| *k == key) {
+        Some((_, kind)) => kind,
+        //Plugins and mods can add their own properties
+        None if properties.get(key).is_some() => &Kind::Text,
+        None => return Err(format!("Unknown property {}", key).into()),
+    };
+    match kind {
+        Kind::Bool if value != "true" && value != "false" => {
+            Err(format!("{} must be true or false", key).into())
+        }
+        Kind::Int(min, max) => match value.parse::<i64>() {
+            Ok(n) if n >= *min && n <= *max => Ok(()),
+            _ => Err(format!("{} must be a number between {} and {}", key, min, max).into()),
+        },
+        Kind::Choice(choices) if !choices.contains(&value) => {
+            Err(format!("{} must be one of: {}", key, choices.join(", ")).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+//Console command that applies the property to a running server, if there is one
+pub fn live_command(key: &str, value: &str) -> Option<String> {
+    match key {
+        "difficulty" => Some(format!("difficulty {}", value)),
+        "white-list" if value == "true" => Some(String::from("whitelist on")),
+        "white-list" => Some(String::from("whitelist off")),
+        _ => None,
+    }
+}
+
+/*
+    props get [key]: shows a property or all of them
+    props set <key> <value>: changes a property
+    props diff: shows the properties that will change after a restart
+*/
+pub fn properties_command(
+    args: &[&str],
+    config: &Config,
+    server: Option<&mut ServerHandler>,
+    user: Option<i64>,
+) {
+    let out = get_output_sender();
+    let mut properties = match ServerProperties::load(&config.server_path()) {
+        Ok(p) => p,
+        Err(e) => {
+            errorln!(out, "Could not read {}: {}", PROPERTIES_FILE, e);
+            return;
+        }
+    };
+    let running = server.as_ref().and_then(|s| s.running_properties());
+
+    match args {
+        ["get"] => {
+            let mut s = String::new();
+            for (key, value) in properties.entries() {
+                s.push_str(&format!("{}={}{}\n", key, value, pending(running, key, value)));
+            }
+            infoln!(out, "{}", s.trim_end());
+        }
+        ["get", key] => match properties.get(key) {
+            Some(value) => {
+                infoln!(out, "{}={}{}", key, value, pending(running, key, value));
+            }
+            None => {
+                warnln!(out, "Property {} is not set", key);
+            }
+        },
+        ["set", key, value @ ..] if !value.is_empty() => {
+            let value = value.join(" ");
+            if let Err(e) = validate(&properties, key, &value) {
+                errorln!(out, "{}", e);
+                return;
+            }
+            //Changing the file has the same effect once the server restarts
+            let command = live_command(key, &value);
+            if let Some(command) = &command {
+                let by = format!("props set {} {}", key, value);
+                if !config.telegram_permissions.allows(user, command, &by) {
+                    return;
+                }
+            }
+            properties.set(key, &value);
+            if let Err(e) = properties.save() {
+                errorln!(out, "Could not write {}: {}", PROPERTIES_FILE, e);
+                return;
+            }
+
+            match (server, command) {
+                (Some(server), Some(command)) => {
+                    if let Err(e) = server.sendln(command.as_bytes()) {
+                        errorln!(out, "Could not apply {} to the running server: {}", key, e);
+                        return;
+                    }
+                    server.set_running_property(key, &value);
+                    infoln!(out, "{}={} (applied)", key, value);
+                }
+                (Some(_), None) => {
+                    infoln!(out, "{}={} (pending until restart)", key, value);
+                }
+                (None, _) => {
+                    infoln!(out, "{}={}", key, value);
+                }
+            }
+        }
+        ["diff"] => match running {
+            Some(running) => {
+                let changes = running.diff(&properties);
+                if changes.is_empty() {
+                    infoln!(out, "The server is running with the current properties");
+                    return;
+                }
+                let mut s = String::from("Pending until restart:\n");
+                for (key, old, new) in changes {
+                    s.push_str(&format!(
+                        "{}: {} -> {}\n",
+                        key,
+                        old.unwrap_or("(unset)"),
+                        new.unwrap_or("(unset)")
+                    ));
+                }
+                infoln!(out, "{}", s.trim_end());
+            }
+            None => {
+                warnln!(out, "The properties the server is running with are unknown");
+            }
+        },
+        _ => {
+            warnln!(out, "Usage: props get [key] | props set <key> <value> | props diff");
+        }
+    }
+}
+
+fn pending(running: Option<&ServerProperties>, key: &str, value: &str) -> &'static str {
+    match running {
+        Some(running) if running.get(key) != Some(value) => " (pending until restart)",
+        _ => "",
+    }
+}
+
+fn parse_line(line: &str) -> Line {
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+        return Line::Raw(String::from(line));
+    }
+
+    //The key ends at the first unescaped separator
+    let mut escaped = false;
+    let mut split = trimmed.len();
+    for (i, c) in trimmed.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '=' || c == ':' || c.is_whitespace() {
+            split = i;
+            break;
+        }
+    }
+    let key = unescape(&trimmed[..split]);
+    let rest = trimmed[split..].trim_start();
+    let rest = rest
+        .strip_prefix('=')
+        .or_else(|| rest.strip_prefix(':'))
+        .unwrap_or(rest)
+        .trim_start();
+
+    Line::Entry {
+        key,
+        value: unescape(rest),
+        text: String::from(line),
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    //\u escapes are UTF-16 code units, characters outside the BMP take two of them
+    let mut units: Vec<u16> = Vec::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.as_str().starts_with('u') {
+            chars.next();
+            let code: String = chars.by_ref().take(4).collect();
+            if let Ok(unit) = u16::from_str_radix(&code, 16) {
+                units.push(unit);
+            }
+            continue;
+        }
+        result.extend(std::char::decode_utf16(units.drain(..)).filter_map(Result::ok));
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('f') => result.push('\u{c}'),
+            Some(c) => result.push(c),
+            None => (),
+        }
+    }
+    result.extend(std::char::decode_utf16(units.drain(..)).filter_map(Result::ok));
+    result
+}
+
+fn escape(s: &str, is_key: bool) -> String {
+    let mut result = String::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            ' ' if is_key || i == 0 => result.push_str("\\ "),
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '=' | ':' | '#' | '!' => {
+                result.push('\\');
+                result.push(c);
+            }
+            c if (c as u32) < 0x20 || (c as u32) > 0x7e => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    result.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn entry(line: &str) -> (String, String) {
+        match parse_line(line) {
+            Line::Entry { key, value, .. } => (key, value),
+            Line::Raw(_) => panic!("{} is not an entry", line),
+        }
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (String::from(key), String::from(value))
+    }
+
+    #[test]
+    fn parses_separators_and_escapes() {
+        assert_eq!(entry("motd=A Minecraft Server"), pair("motd", "A Minecraft Server"));
+        assert_eq!(entry("  level-name : world"), pair("level-name", "world"));
+        assert_eq!(entry("level-seed 1234"), pair("level-seed", "1234"));
+        assert_eq!(entry("a\\=b=c\\:d"), pair("a=b", "c:d"));
+        assert_eq!(entry("motd=\\u00A7cRed \\uD83D\\uDE00"), pair("motd", "\u{a7}cRed \u{1f600}"));
+        assert_eq!(entry("resource-pack="), pair("resource-pack", ""));
+        assert!(matches!(parse_line("#Minecraft server properties"), Line::Raw(_)));
+        assert!(matches!(parse_line("! comment"), Line::Raw(_)));
+        assert!(matches!(parse_line("   "), Line::Raw(_)));
+    }
+
+    #[test]
+    fn escaped_entries_round_trip() {
+        let values = [" leading space", "a=b:c#d!e", "tab\there\nnew line", "back\\slash", "\u{a7}c\u{1f600}"];
+        for value in &values {
+            let line = format!("{}={}", escape("some key", true), escape(value, false));
+            assert_eq!(entry(&line), pair("some key", value));
+        }
+    }
+
+    #[test]
+    fn set_keeps_the_other_lines_as_they_were() {
+        let directory = env::temp_dir().join(format!("server_manager_properties_{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join(PROPERTIES_FILE),
+            "#Minecraft server properties\nmotd=Old \\u00A7c\npvp=true\n",
+        )
+        .unwrap();
+
+        let mut properties = ServerProperties::load(&directory).unwrap();
+        properties.set("pvp", "false");
+        properties.set("level-name", "new world");
+        properties.save().unwrap();
+        let saved = fs::read_to_string(directory.join(PROPERTIES_FILE)).unwrap();
+        let reloaded = ServerProperties::load(&directory).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            saved,
+            "#Minecraft server properties\nmotd=Old \\u00A7c\npvp=false\nlevel-name=new world\n"
+        );
+        assert_eq!(reloaded.get("motd"), Some("Old \u{a7}c"));
+        assert_eq!(reloaded.get("level-name"), Some("new world"));
+        assert!(properties.diff(&reloaded).is_empty());
+    }
+
+    #[test]
+    fn validates_known_properties() {
+        let properties = ServerProperties {
+            path: PathBuf::new(),
+            lines: vec![parse_line("custom-plugin-setting=1")],
+        };
+        assert!(validate(&properties, "pvp", "false").is_ok());
+        assert!(validate(&properties, "pvp", "yes").is_err());
+        assert!(validate(&properties, "view-distance", "33").is_err());
+        assert!(validate(&properties, "difficulty", "hard").is_ok());
+        assert!(validate(&properties, "difficulty", "extreme").is_err());
+        assert!(validate(&properties, "custom-plugin-setting", "anything").is_ok());
+        assert!(validate(&properties, "unknown-key", "1").is_err());
+    }
+}
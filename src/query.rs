@@ -0,0 +1,263 @@
+use crate::ping::split_address;
+use crate::*;
+
+use std::{
+    net::{ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+/*
+    GameSpy4 Query protocol, enabled with enable-query=true in server.properties.
+    See https://wiki.vg/Query
+*/
+
+const MAGIC: [u8; 2] = [0xFE, 0xFD];
+const HANDSHAKE_TYPE: u8 = 9;
+const STAT_TYPE: u8 = 0;
+const TIMEOUT: u64 = 5;
+const MAX_RESPONSE_LENGTH: usize = 1 << 16;
+
+//Paddings sent by the server before the K/V section and before the player list
+const FULL_STAT_KV_PADDING: usize = 11;
+const FULL_STAT_PLAYERS_PADDING: usize = 10;
+
+pub struct BasicStat {
+    pub motd: String,
+    pub game_type: String,
+    pub map: String,
+    pub online_players: u32,
+    pub max_players: u32,
+}
+
+pub struct FullStat {
+    pub motd: String,
+    pub game_type: String,
+    pub version: String,
+    pub plugins: String,
+    pub map: String,
+    pub online_players: u32,
+    pub max_players: u32,
+    pub players: Vec<String>,
+}
+
+struct QuerySession {
+    socket: UdpSocket,
+    session_id: i32,
+    challenge: i32,
+}
+
+pub fn basic_stat(address: &str) -> GenericResult<BasicStat> {
+    let mut session = QuerySession::handshake(address)?;
+    let response = session.request(&[])?;
+    let mut pos = 0;
+
+    Ok(BasicStat {
+        motd: read_cstring(&response, &mut pos)?,
+        game_type: read_cstring(&response, &mut pos)?,
+        map: read_cstring(&response, &mut pos)?,
+        online_players: parse_number(&read_cstring(&response, &mut pos)?)?,
+        max_players: parse_number(&read_cstring(&response, &mut pos)?)?,
+    })
+}
+
+pub fn full_stat(address: &str) -> GenericResult<FullStat> {
+    let mut session = QuerySession::handshake(address)?;
+    let response = session.request(&[0, 0, 0, 0])?;
+
+    let mut stat = FullStat {
+        motd: String::new(),
+        game_type: String::new(),
+        version: String::new(),
+        plugins: String::new(),
+        map: String::new(),
+        online_players: 0,
+        max_players: 0,
+        players: Vec::new(),
+    };
+
+    //K/V section ends with an empty key
+    let mut pos = FULL_STAT_KV_PADDING;
+    loop {
+        let key = read_cstring(&response, &mut pos)?;
+        if key.is_empty() {
+            break;
+        }
+        let value = read_cstring(&response, &mut pos)?;
+        match key.as_str() {
+            "hostname" => stat.motd = value,
+            "gametype" => stat.game_type = value,
+            "version" => stat.version = value,
+            "plugins" => stat.plugins = value,
+            "map" => stat.map = value,
+            "numplayers" => stat.online_players = parse_number(&value)?,
+            "maxplayers" => stat.max_players = parse_number(&value)?,
+            _ => (),
+        }
+    }
+
+    //Player list ends with an empty name
+    pos += FULL_STAT_PLAYERS_PADDING;
+    while pos < response.len() {
+        let player = read_cstring(&response, &mut pos)?;
+        if player.is_empty() {
+            break;
+        }
+        stat.players.push(player);
+    }
+
+    Ok(stat)
+}
+
+impl QuerySession {
+    fn handshake(address: &str) -> GenericResult<Self> {
+        let (host, port) = split_address(address)?;
+        let socket_addr = (host.as_str(), port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| GenericError::from(format!("Could not resolve {}", host)))?;
+
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_read_timeout(Some(Duration::from_secs(TIMEOUT)))?;
+        socket.connect(socket_addr)?;
+
+        //Only the lower 4 bits of each byte are used by the server
+        let session_id = (chrono::Local::now().timestamp_subsec_nanos() as i32) & 0x0F0F_0F0F;
+        let mut session = Self {
+            socket,
+            session_id,
+            challenge: 0,
+        };
+
+        let response = session.exchange(HANDSHAKE_TYPE, &[])?;
+        let token = response.split(|b| *b == 0).next().unwrap_or(&[]);
+        session.challenge = String::from_utf8_lossy(token)
+            .trim()
+            .parse::<i32>()
+            .map_err(|_| GenericError::from("Invalid query challenge token"))?;
+        Ok(session)
+    }
+
+    fn request(&mut self, padding: &[u8]) -> GenericResult<Vec<u8>> {
+        let mut payload = Vec::from(&self.challenge.to_be_bytes()[..]);
+        payload.extend_from_slice(padding);
+        self.exchange(STAT_TYPE, &payload)
+    }
+
+    fn exchange(&mut self, kind: u8, payload: &[u8]) -> GenericResult<Vec<u8>> {
+        let mut packet = Vec::with_capacity(7 + payload.len());
+        packet.extend_from_slice(&MAGIC);
+        packet.push(kind);
+        packet.extend_from_slice(&self.session_id.to_be_bytes());
+        packet.extend_from_slice(payload);
+        self.socket.send(&packet)?;
+
+        let mut buf = vec![0u8; MAX_RESPONSE_LENGTH];
+        let length = self.socket.recv(&mut buf)?;
+        if length < 5 || buf[0] != kind || buf[1..5] != self.session_id.to_be_bytes() {
+            return Err("Unexpected query response".into());
+        }
+        buf.truncate(length);
+        Ok(buf.split_off(5))
+    }
+}
+
+fn parse_number(s: &str) -> GenericResult<u32> {
+    s.trim()
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid number in query response: {}", s).into())
+}
+
+fn read_cstring(buf: &[u8], pos: &mut usize) -> GenericResult<String> {
+    let rest = buf
+        .get(*pos..)
+        .ok_or_else(|| GenericError::from("Truncated query response"))?;
+    let end = rest
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| GenericError::from("Truncated query response"))?;
+    *pos += end + 1;
+    Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const CHALLENGE: i32 = 9513307;
+
+    fn full_stat_response() -> Vec<u8> {
+        let mut response = b"splitnum\0\x80\0".to_vec();
+        for (key, value) in &[
+            ("hostname", "A Minecraft Server"),
+            ("gametype", "SMP"),
+            ("game_id", "MINECRAFT"),
+            ("version", "1.20.4"),
+            ("plugins", "Paper on 1.20.4: WorldEdit 7.2.15"),
+            ("map", "world"),
+            ("numplayers", "2"),
+            ("maxplayers", "20"),
+        ] {
+            response.extend_from_slice(key.as_bytes());
+            response.push(0);
+            response.extend_from_slice(value.as_bytes());
+            response.push(0);
+        }
+        response.extend_from_slice(b"\0\x01player_\0\0Steve\0Alex\0\0");
+        response
+    }
+
+    //A server that answers the given number of packets
+    fn fake_server(packets: usize) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            for _ in 0..packets {
+                let (length, client) = socket.recv_from(&mut buf).unwrap();
+                assert_eq!(buf[..2], MAGIC);
+                let mut response = buf[2..7].to_vec();
+                match (buf[2], length) {
+                    (HANDSHAKE_TYPE, 7) => response.extend_from_slice(format!("{}\0", CHALLENGE).as_bytes()),
+                    (STAT_TYPE, 11) => {
+                        assert_eq!(buf[7..11], CHALLENGE.to_be_bytes());
+                        response.extend_from_slice(b"A Minecraft Server\0SMP\0world\x002\x0020\0\xdd\x63127.0.0.1\0");
+                    }
+                    (STAT_TYPE, 15) => response.extend(full_stat_response()),
+                    _ => panic!("Unexpected query packet"),
+                }
+                socket.send_to(&response, client).unwrap();
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn reads_strings_and_numbers() {
+        let buf = b"abc\0\0de";
+        let mut pos = 0;
+        assert_eq!(read_cstring(buf, &mut pos).unwrap(), "abc");
+        assert_eq!(read_cstring(buf, &mut pos).unwrap(), "");
+        assert_eq!(pos, 5);
+        assert!(read_cstring(buf, &mut pos).is_err());
+        assert!(read_cstring(buf, &mut 10).is_err());
+        assert_eq!(parse_number(" 20 ").unwrap(), 20);
+        assert!(parse_number("-1").is_err());
+    }
+
+    #[test]
+    fn queries_the_basic_and_full_stats() {
+        let address = fake_server(4);
+        let basic = basic_stat(&address).unwrap();
+        assert_eq!((basic.motd.as_str(), basic.game_type.as_str(), basic.map.as_str()), ("A Minecraft Server", "SMP", "world"));
+        assert_eq!((basic.online_players, basic.max_players), (2, 20));
+
+        let full = full_stat(&address).unwrap();
+        assert_eq!(full.motd, "A Minecraft Server");
+        assert_eq!(full.version, "1.20.4");
+        assert_eq!(full.plugins, "Paper on 1.20.4: WorldEdit 7.2.15");
+        assert_eq!(full.map, "world");
+        assert_eq!((full.online_players, full.max_players), (2, 20));
+        assert_eq!(full.players, vec!["Steve", "Alex"]);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::*;
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+const SERVERDATA_AUTH: i32 = 3;
+const SERVERDATA_AUTH_RESPONSE: i32 = 2;
+const SERVERDATA_EXECCOMMAND: i32 = 2;
+const SERVERDATA_RESPONSE_VALUE: i32 = 0;
+
+//Minecraft drops the connection if a client sends a bigger payload
+const MAX_COMMAND_LENGTH: usize = 1446;
+const MAX_PACKET_LENGTH: i32 = 1 << 16;
+const READ_TIMEOUT: u64 = 30;
+
+struct Packet {
+    id: i32,
+    kind: i32,
+    body: String,
+}
+
+pub struct RconClient {
+    stream: TcpStream,
+    last_id: i32,
+}
+
+impl RconClient {
+    pub fn connect(address: &str, password: &str) -> GenericResult<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT)))?;
+        let mut client = Self { stream, last_id: 0 };
+        client.authenticate(password)?;
+        Ok(client)
+    }
+
+    fn authenticate(&mut self, password: &str) -> GenericResult<()> {
+        let id = self.next_id();
+        self.write_packet(id, SERVERDATA_AUTH, password)?;
+        loop {
+            //Some implementations send an empty response value before the auth response
+            let packet = self.read_packet()?;
+            if packet.kind != SERVERDATA_AUTH_RESPONSE {
+                continue;
+            }
+            if packet.id == -1 {
+                return Err("RCON authentication failed. Check the password".into());
+            }
+            if packet.id == id {
+                return Ok(());
+            }
+        }
+    }
+
+    /*
+        Responses bigger than a packet are split by the server without
+        any end marker, so an invalid request is sent after the command.
+        The server answers requests in order, so once the answer to the
+        second one arrives the whole response has been read.
+    */
+    pub fn command(&mut self, command: &str) -> GenericResult<String> {
+        if command.len() > MAX_COMMAND_LENGTH {
+            return Err(format!(
+                "RCON commands can't be longer than {} bytes",
+                MAX_COMMAND_LENGTH
+            )
+            .into());
+        }
+        let id = self.next_id();
+        let end_id = self.next_id();
+        self.write_packet(id, SERVERDATA_EXECCOMMAND, command)?;
+        self.write_packet(end_id, SERVERDATA_RESPONSE_VALUE, "")?;
+
+        let mut response = String::new();
+        loop {
+            let packet = self.read_packet()?;
+            if packet.id == end_id {
+                break;
+            }
+            if packet.id == id {
+                response.push_str(&packet.body);
+            }
+        }
+        Ok(response)
+    }
+
+    fn next_id(&mut self) -> i32 {
+        //Negative ids are reserved for authentication failures
+        self.last_id = self.last_id.checked_add(1).unwrap_or(1);
+        self.last_id
+    }
+
+    fn write_packet(&mut self, id: i32, kind: i32, body: &str) -> GenericResult<()> {
+        let length = (4 + 4 + body.len() + 2) as i32;
+        let mut buf = Vec::with_capacity(length as usize + 4);
+        buf.extend_from_slice(&length.to_le_bytes());
+        buf.extend_from_slice(&id.to_le_bytes());
+        buf.extend_from_slice(&kind.to_le_bytes());
+        buf.extend_from_slice(body.as_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    fn read_packet(&mut self) -> GenericResult<Packet> {
+        let mut int_buf = [0u8; 4];
+        self.stream.read_exact(&mut int_buf)?;
+        let length = i32::from_le_bytes(int_buf);
+        if !(10..=MAX_PACKET_LENGTH).contains(&length) {
+            return Err(format!("Received an RCON packet with invalid length {}", length).into());
+        }
+
+        let mut buf = vec![0u8; length as usize];
+        self.stream.read_exact(&mut buf)?;
+        let id = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let kind = i32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let body = &buf[8..];
+        let end = body.iter().position(|b| *b == 0).unwrap_or(body.len());
+        let body = String::from_utf8_lossy(&body[..end]).into_owned();
+
+        Ok(Packet { id, kind, body })
+    }
+}
@@ -0,0 +1,588 @@
+use crate::backup::backup;
+use crate::io::*;
+use crate::nbt::*;
+use crate::properties::world_path;
+use crate::restart::parse_duration;
+use crate::world::LevelData;
+use crate::*;
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/*
+    Anvil region files store 32x32 chunks. They start with a table of
+    1024 locations (offset in sectors of 4 KiB and number of sectors)
+    followed by a table of timestamps. Every chunk starts with its
+    length and compression type
+*/
+
+const SECTOR_SIZE: usize = 4096;
+const HEADER_SIZE: usize = 2 * SECTOR_SIZE;
+const CHUNKS_PER_REGION: usize = 1024;
+const REGION_WIDTH: i64 = 32;
+const CHUNK_WIDTH: i64 = 16;
+const TICKS_PER_SECOND: u64 = 20;
+
+const GZIP_COMPRESSION: u8 = 1;
+const ZLIB_COMPRESSION: u8 = 2;
+const NO_COMPRESSION: u8 = 3;
+
+const OVERWORLD: &str = "overworld";
+const NETHER: &str = "the_nether";
+const END: &str = "the_end";
+//Directories with region files. Entities and POIs are pruned along with the chunks
+const REGION_DIRECTORY: &str = "region";
+const CHUNK_DATA_DIRECTORIES: [&str; 2] = ["entities", "poi"];
+
+/********** Protected areas **********/
+
+#[derive(Clone)]
+pub struct ProtectedArea {
+    dimension: String,
+    min_x: i64,
+    min_z: i64,
+    max_x: i64,
+    max_z: i64,
+}
+
+impl ProtectedArea {
+    //"<dimension> <x1> <z1> <x2> <z2>" in blocks
+    pub fn parse(s: &str) -> Option<ProtectedArea> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        match parts.as_slice() {
+            [dimension, x1, z1, x2, z2] => {
+                let (x1, z1, x2, z2): (i64, i64, i64, i64) = (
+                    x1.parse().ok()?,
+                    z1.parse().ok()?,
+                    x2.parse().ok()?,
+                    z2.parse().ok()?,
+                );
+                Some(ProtectedArea {
+                    dimension: String::from(*dimension),
+                    min_x: x1.min(x2),
+                    min_z: z1.min(z2),
+                    max_x: x1.max(x2),
+                    max_z: z1.max(z2),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn contains(&self, dimension: &str, chunk_x: i64, chunk_z: i64) -> bool {
+        let (x, z) = (chunk_x * CHUNK_WIDTH, chunk_z * CHUNK_WIDTH);
+        self.dimension == dimension
+            && x + CHUNK_WIDTH > self.min_x
+            && x <= self.max_x
+            && z + CHUNK_WIDTH > self.min_z
+            && z <= self.max_z
+    }
+}
+
+/********** Dimensions **********/
+
+pub struct Dimension {
+    pub name: String,
+    //Directory with the region, entities and poi directories
+    pub path: PathBuf,
+}
+
+//Vanilla keeps every dimension inside the world. Bukkit servers use a world for each one
+pub fn find_dimensions(world: &Path) -> Vec<Dimension> {
+    let world_name = world
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let parent = world.parent().map(PathBuf::from).unwrap_or_default();
+    let candidates = vec![
+        (String::from(OVERWORLD), world.to_path_buf()),
+        (String::from(NETHER), world.join("DIM-1")),
+        (String::from(END), world.join("DIM1")),
+        (
+            String::from(NETHER),
+            parent.join(format!("{}_nether", world_name)).join("DIM-1"),
+        ),
+        (
+            String::from(END),
+            parent.join(format!("{}_the_end", world_name)).join("DIM1"),
+        ),
+    ];
+
+    let mut dimensions: Vec<Dimension> = candidates
+        .into_iter()
+        .filter(|(_, path)| path.join(REGION_DIRECTORY).is_dir())
+        .map(|(name, path)| Dimension { name, path })
+        .collect();
+
+    //Datapacks can add dimensions in dimensions/<namespace>/<name>
+    if let Ok(namespaces) = fs::read_dir(world.join("dimensions")) {
+        for namespace in namespaces.filter_map(|e| e.ok()) {
+            if let Ok(entries) = fs::read_dir(namespace.path()) {
+                for entry in entries.filter_map(|e| e.ok()) {
+                    if entry.path().join(REGION_DIRECTORY).is_dir() {
+                        dimensions.push(Dimension {
+                            name: format!(
+                                "{}:{}",
+                                namespace.file_name().to_string_lossy(),
+                                entry.file_name().to_string_lossy()
+                            ),
+                            path: entry.path(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    dimensions
+}
+
+/********** Region files **********/
+
+pub struct RegionFile {
+    pub path: PathBuf,
+    pub x: i64,
+    pub z: i64,
+    data: Vec<u8>,
+}
+
+impl RegionFile {
+    pub fn load(path: &Path) -> GenericResult<RegionFile> {
+        let (x, z) = region_coordinates(path).ok_or("Invalid region file name")?;
+        let data = fs::read(path)?;
+        if !data.is_empty() && data.len() < HEADER_SIZE {
+            return Err(format!("{} is truncated", path.display()).into());
+        }
+        Ok(RegionFile {
+            path: PathBuf::from(path),
+            x,
+            z,
+            data,
+        })
+    }
+
+    //Offset and sectors of the chunk, if it exists
+    fn location(&self, index: usize) -> Option<(usize, usize)> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let entry = &self.data[index * 4..index * 4 + 4];
+        let offset = u32::from_be_bytes([0, entry[0], entry[1], entry[2]]) as usize;
+        let sectors = entry[3] as usize;
+        if offset < 2 || sectors == 0 || offset * SECTOR_SIZE >= self.data.len() {
+            None
+        } else {
+            Some((offset, sectors))
+        }
+    }
+
+    pub fn chunks(&self) -> Vec<usize> {
+        (0..CHUNKS_PER_REGION)
+            .filter(|i| self.location(*i).is_some())
+            .collect()
+    }
+
+    pub fn chunk_coordinates(&self, index: usize) -> (i64, i64) {
+        (
+            self.x * REGION_WIDTH + (index as i64 % REGION_WIDTH),
+            self.z * REGION_WIDTH + (index as i64 / REGION_WIDTH),
+        )
+    }
+
+    pub fn read_chunk(&self, index: usize) -> GenericResult<Tag> {
+        let (offset, _) = self.location(index).ok_or("The chunk doesn't exist")?;
+        let start = offset * SECTOR_SIZE;
+        let header = self
+            .data
+            .get(start..start + 5)
+            .ok_or("The chunk is outside the file")?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let compression = header[4];
+        let payload = self
+            .data
+            .get(start + 5..start + 4 + length)
+            .ok_or("The chunk is truncated")?;
+        match compression {
+            GZIP_COMPRESSION => read_nbt(&mut GzDecoder::new(payload)),
+            ZLIB_COMPRESSION => read_nbt(&mut ZlibDecoder::new(payload)),
+            NO_COMPRESSION => read_nbt(&mut { payload }),
+            //LZ4 and chunks stored in their own .mcc file
+            _ => Err(format!("Unsupported chunk compression {}", compression).into()),
+        }
+    }
+
+    //Writes the file again without the removed chunks, leaving no free sectors
+    pub fn remove_chunks(&self, removed: &HashSet<usize>) -> GenericResult<()> {
+        let kept: Vec<usize> = self
+            .chunks()
+            .into_iter()
+            .filter(|i| !removed.contains(i))
+            .collect();
+        if kept.is_empty() {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+
+        let mut data = vec![0; HEADER_SIZE];
+        for i in kept {
+            let (offset, sectors) = self.location(i).unwrap();
+            let new_offset = data.len() / SECTOR_SIZE;
+            let start = offset * SECTOR_SIZE;
+            let end = (start + sectors * SECTOR_SIZE).min(self.data.len());
+            data.extend_from_slice(&self.data[start..end]);
+            data.resize(new_offset * SECTOR_SIZE + sectors * SECTOR_SIZE, 0);
+
+            let location = (new_offset as u32).to_be_bytes();
+            data[i * 4..i * 4 + 4].copy_from_slice(&[
+                location[1],
+                location[2],
+                location[3],
+                sectors as u8,
+            ]);
+            let timestamp = SECTOR_SIZE + i * 4;
+            data[timestamp..timestamp + 4].copy_from_slice(&self.data[timestamp..timestamp + 4]);
+        }
+
+        let mut temporary = self.path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+//r.<x>.<z>.mca
+fn region_coordinates(path: &Path) -> Option<(i64, i64)> {
+    let name = path.file_name()?.to_str()?;
+    let parts: Vec<&str> = name.split('.').collect();
+    match parts.as_slice() {
+        ["r", x, z, "mca"] => Some((x.parse().ok()?, z.parse().ok()?)),
+        _ => None,
+    }
+}
+
+fn list_region_files(directory: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| region_coordinates(p).is_some())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
+
+//Stored in the root since 1.18 and in Level before
+fn inhabited_time(chunk: &Tag) -> Option<i64> {
+    chunk
+        .get("InhabitedTime")
+        .or_else(|| chunk.get_path(&["Level", "InhabitedTime"]))
+        .and_then(Tag::as_i64)
+}
+
+/********** Analysis and pruning **********/
+
+struct Pruning {
+    min_inhabited_ticks: i64,
+    spawn: Option<(i64, i64)>,
+    spawn_radius: i64,
+    areas: Vec<ProtectedArea>,
+}
+
+impl Pruning {
+    fn new(config: &Config, world: &Path, min_inhabited_time: u64) -> Pruning {
+        Pruning {
+            min_inhabited_ticks: (min_inhabited_time * TICKS_PER_SECOND) as i64,
+            spawn: LevelData::load(world)
+                .ok()
+                .and_then(|l| l.spawn)
+                .map(|(x, _, z)| (x, z)),
+            spawn_radius: config.pruning_spawn_radius,
+            areas: config.pruning_protected_areas.clone(),
+        }
+    }
+
+    fn protected(&self, dimension: &str, chunk_x: i64, chunk_z: i64) -> bool {
+        let near_spawn = match self.spawn {
+            Some((x, z)) if dimension == OVERWORLD => {
+                let (cx, cz) = (
+                    chunk_x * CHUNK_WIDTH + CHUNK_WIDTH / 2,
+                    chunk_z * CHUNK_WIDTH + CHUNK_WIDTH / 2,
+                );
+                (cx - x).abs() <= self.spawn_radius && (cz - z).abs() <= self.spawn_radius
+            }
+            _ => false,
+        };
+        near_spawn
+            || self
+                .areas
+                .iter()
+                .any(|a| a.contains(dimension, chunk_x, chunk_z))
+    }
+
+    //Chunks that can't be read are kept
+    fn prunable(&self, dimension: &str, region: &RegionFile) -> HashSet<usize> {
+        region
+            .chunks()
+            .into_iter()
+            .filter(|i| {
+                let (x, z) = region.chunk_coordinates(*i);
+                !self.protected(dimension, x, z)
+                    && region
+                        .read_chunk(*i)
+                        .ok()
+                        .and_then(|c| inhabited_time(&c))
+                        .map(|t| t < self.min_inhabited_ticks)
+                        .unwrap_or(false)
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct DimensionStats {
+    files: usize,
+    chunks: usize,
+    bytes: u64,
+    prunable: usize,
+}
+
+fn analyse(dimension: &Dimension, pruning: &Pruning, prune: bool) -> DimensionStats {
+    let out = get_output_sender();
+    let mut stats = DimensionStats::default();
+    for path in list_region_files(&dimension.path.join(REGION_DIRECTORY)) {
+        let region = match RegionFile::load(&path) {
+            Ok(region) => region,
+            Err(e) => {
+                warnln!(out, "Skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let prunable = pruning.prunable(&dimension.name, &region);
+        stats.files += 1;
+        stats.chunks += region.chunks().len();
+        stats.bytes += region.data.len() as u64;
+        stats.prunable += prunable.len();
+
+        if prune && !prunable.is_empty() {
+            let mut result = region.remove_chunks(&prunable);
+            for directory in CHUNK_DATA_DIRECTORIES.iter() {
+                let path = dimension
+                    .path
+                    .join(directory)
+                    .join(path.file_name().unwrap());
+                if path.exists() {
+                    result = result.and_then(|_| RegionFile::load(&path)?.remove_chunks(&prunable));
+                }
+            }
+            if let Err(e) = result {
+                errorln!(out, "Could not prune {}: {}", path.display(), e);
+            }
+        }
+    }
+    stats
+}
+
+fn format_size(bytes: u64) -> String {
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    if mb >= 1024.0 {
+        format!("{:.1} GiB", mb / 1024.0)
+    } else {
+        format!("{:.1} MiB", mb)
+    }
+}
+
+/*
+    regions report: chunks and size of every dimension, and how many chunks can be pruned
+    regions prune [min time]: removes the chunks where players have spent less time
+
+    Pruning needs the server to be stopped and a backup is always made first
+*/
+pub fn regions_command(args: &[&str], config: &Config, server_running: bool) {
+    let out = get_output_sender();
+    let world = world_path(config);
+    let (prune, min_time) = match args {
+        ["report"] => (false, None),
+        ["prune"] => (true, None),
+        ["prune", time] => match parse_duration(time) {
+            Some(time) => (true, Some(time)),
+            None => {
+                warnln!(out, "Invalid time {}", time);
+                return;
+            }
+        },
+        _ => {
+            warnln!(
+                out,
+                "Usage: regions report | regions prune [min inhabited time]"
+            );
+            return;
+        }
+    };
+    if prune && server_running {
+        warnln!(out, "Stop the server before pruning the world");
+        return;
+    }
+
+    let dimensions = find_dimensions(&world);
+    if dimensions.is_empty() {
+        warnln!(out, "There are no region files in {}", world.display());
+        return;
+    }
+    let min_time = min_time.unwrap_or(config.pruning_min_inhabited_time);
+    let pruning = Pruning::new(config, &world, min_time);
+
+    if prune {
+        infoln!(out, "Creating backup...");
+        if let Err(e) = backup(config) {
+            errorln!(
+                out,
+                "Could not make backup. Pruning cancelled. Error: {}",
+                e
+            );
+            return;
+        }
+    } else if server_running {
+        warnln!(
+            out,
+            "The server is running, so the region files could change while they are read"
+        );
+    }
+
+    for dimension in &dimensions {
+        let stats = analyse(dimension, &pruning, prune);
+        infoln!(
+            out,
+            "{}: {} chunks in {} region files ({}). {} chunks {} less than {} seconds inhabited",
+            dimension.name,
+            stats.chunks,
+            stats.files,
+            format_size(stats.bytes),
+            stats.prunable,
+            if prune {
+                "pruned with"
+            } else {
+                "can be pruned with"
+            },
+            min_time
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    //Uncompressed compound with InhabitedTime, as stored since 1.18
+    fn chunk(inhabited_time: i64) -> Vec<u8> {
+        let mut nbt = vec![10, 0, 0, 4, 0, 13];
+        nbt.extend_from_slice(b"InhabitedTime");
+        nbt.extend_from_slice(&inhabited_time.to_be_bytes());
+        nbt.push(0);
+        let mut data = ((nbt.len() + 1) as u32).to_be_bytes().to_vec();
+        data.push(NO_COMPRESSION);
+        data.extend(nbt);
+        data
+    }
+
+    //Chunks given as (index, offset, sectors, inhabited time)
+    fn region(chunks: &[(usize, usize, usize, i64)]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        for &(index, offset, sectors, time) in chunks {
+            let location = (offset as u32).to_be_bytes();
+            data[index * 4..index * 4 + 4].copy_from_slice(&[location[1], location[2], location[3], sectors as u8]);
+            let timestamp = SECTOR_SIZE + index * 4;
+            data[timestamp..timestamp + 4].copy_from_slice(&(index as u32 + 1000).to_be_bytes());
+            data.resize(data.len().max((offset + sectors) * SECTOR_SIZE), 0);
+            let bytes = chunk(time);
+            data[offset * SECTOR_SIZE..offset * SECTOR_SIZE + bytes.len()].copy_from_slice(&bytes);
+        }
+        data
+    }
+
+    fn inhabited(region: &RegionFile, index: usize) -> Option<i64> {
+        inhabited_time(&region.read_chunk(index).ok()?)
+    }
+
+    #[test]
+    fn reads_coordinates_and_header() {
+        assert_eq!(region_coordinates(Path::new("world/region/r.-1.2.mca")), Some((-1, 2)));
+        assert_eq!(region_coordinates(Path::new("r.0.0.mcc")), None);
+        assert_eq!(region_coordinates(Path::new("r.a.0.mca")), None);
+
+        let region = RegionFile {
+            path: PathBuf::from("r.1.-1.mca"),
+            x: 1,
+            z: -1,
+            data: region(&[(0, 2, 1, 10), (33, 3, 2, 20)]),
+        };
+        assert_eq!(region.chunks(), vec![0, 33]);
+        assert_eq!(region.location(33), Some((3, 2)));
+        assert_eq!(region.chunk_coordinates(33), (33, -31));
+        assert_eq!(inhabited(&region, 0), Some(10));
+        assert_eq!(inhabited(&region, 33), Some(20));
+        assert!(region.read_chunk(1).is_err());
+    }
+
+    #[test]
+    fn ignores_locations_outside_the_file() {
+        let mut data = region(&[(0, 2, 1, 10)]);
+        data[4..8].copy_from_slice(&[0, 0, 9, 1]);
+        data[8..12].copy_from_slice(&[0, 0, 1, 1]);
+        let region = RegionFile {
+            path: PathBuf::from("r.0.0.mca"),
+            x: 0,
+            z: 0,
+            data,
+        };
+        assert_eq!(region.chunks(), vec![0]);
+    }
+
+    #[test]
+    fn remove_chunks_rewrites_the_file() {
+        let directory = env::temp_dir().join(format!("server_manager_region_{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("r.0.0.mca");
+        fs::write(&path, region(&[(0, 2, 1, 10), (5, 3, 2, 20), (40, 5, 1, 30)])).unwrap();
+        fs::write(directory.join("r.0.1.mca"), vec![0; 100]).unwrap();
+        assert!(RegionFile::load(&directory.join("r.0.1.mca")).is_err());
+
+        RegionFile::load(&path)
+            .unwrap()
+            .remove_chunks(&[5].iter().copied().collect())
+            .unwrap();
+        let region = RegionFile::load(&path).unwrap();
+        assert_eq!(region.data.len(), HEADER_SIZE + 2 * SECTOR_SIZE);
+        assert_eq!(region.chunks(), vec![0, 40]);
+        assert_eq!(region.location(0), Some((2, 1)));
+        assert_eq!(region.location(40), Some((3, 1)));
+        assert_eq!(inhabited(&region, 0), Some(10));
+        assert_eq!(inhabited(&region, 40), Some(30));
+        let timestamp = SECTOR_SIZE + 40 * 4;
+        assert_eq!(region.data[timestamp..timestamp + 4], 1040u32.to_be_bytes());
+        assert_eq!(region.data[SECTOR_SIZE + 5 * 4..SECTOR_SIZE + 5 * 4 + 4], [0; 4]);
+
+        region.remove_chunks(&[0, 40].iter().copied().collect()).unwrap();
+        assert!(!path.exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn protected_areas_contain_the_chunks_they_touch() {
+        let area = ProtectedArea::parse("overworld 20 -5 -20 40").unwrap();
+        assert!(area.contains(OVERWORLD, 0, 0));
+        assert!(area.contains(OVERWORLD, -2, -1));
+        assert!(area.contains(OVERWORLD, 1, 2));
+        assert!(!area.contains(OVERWORLD, 2, 0));
+        assert!(!area.contains(OVERWORLD, 0, 3));
+        assert!(!area.contains(NETHER, 0, 0));
+        assert!(ProtectedArea::parse("overworld 1 2 3").is_none());
+        assert!(ProtectedArea::parse("overworld 1 2 3 x").is_none());
+    }
+}
@@ -0,0 +1,193 @@
+use crate::io::*;
+use crate::maintenance::in_maintenance;
+use crate::*;
+
+use chrono::{Duration as ChronoDuration, Local, NaiveTime};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+pub const RESTART_NOW_COMMAND: &str = "restart now";
+
+/*
+    Countdowns and schedules don't own the server handler, they send
+    commands through the input channel as if an user had written them.
+    The main loop ends up sending the warnings to the server and
+    restarting it when the countdown sends "restart now"
+*/
+
+pub struct RestartCountdown {
+    cancelled: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl RestartCountdown {
+    pub fn start(delay: u64, reason: &str, config: &Config) -> RestartCountdown {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancelled_c = cancelled.clone();
+        let reason = String::from(reason);
+        let template = config.restart_warning_command.clone();
+        let mut warnings: Vec<u64> = config
+            .restart_warnings
+            .iter()
+            .cloned()
+            .filter(|w| *w <= delay)
+            .collect();
+        warnings.sort_unstable_by(|a, b| b.cmp(a));
+
+        let input = get_input_sender();
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            for w in warnings {
+                if !sleep_until(start + Duration::from_secs(delay - w), &cancelled_c) {
+                    return;
+                }
+                let command = template
+                    .replace("{time}", &format_duration(w))
+                    .replace("{reason}", &reason);
+                input.send(InputPacket::Command(command + "\n", None)).unwrap();
+            }
+            if sleep_until(start + Duration::from_secs(delay), &cancelled_c) {
+                input
+                    .send(InputPacket::Command(String::from(RESTART_NOW_COMMAND) + "\n", None))
+                    .unwrap();
+            }
+        });
+
+        RestartCountdown { cancelled, handle }
+    }
+
+    pub fn cancel(self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.handle.thread().unpark();
+    }
+}
+
+//Returns false if the countdown was cancelled while sleeping
+fn sleep_until(deadline: Instant, cancelled: &AtomicBool) -> bool {
+    loop {
+        if cancelled.load(Ordering::SeqCst) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::park_timeout(deadline - now);
+    }
+}
+
+//The countdown is started in advance so that the restart itself happens at the configured time
+pub fn schedule_daily_restart(time: NaiveTime, config: &Config) {
+    let lead = config.restart_warnings.iter().cloned().max().unwrap_or(0);
+    let server_path = config.server_path();
+    let out = get_output_sender();
+    let input = get_input_sender();
+    thread::spawn(move || {
+        loop {
+            let now = Local::now().naive_local();
+            let mut next = now.date().and_time(time) - ChronoDuration::seconds(lead as i64);
+            while next <= now {
+                next += ChronoDuration::days(1);
+            }
+            let wait = (next - now).to_std().unwrap_or_default();
+            thread::sleep(wait);
+            if in_maintenance(&server_path) {
+                infoln!(out, "Daily restart skipped, maintenance mode is on");
+                continue;
+            }
+            let command = format!("restart {} Daily restart\n", format_duration(lead));
+            if input.send(InputPacket::Command(command, None)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+//Durations are written as 10s, 5m, 2h or combinations like 1m30s. Plain numbers are seconds
+pub fn parse_duration(s: &str) -> Option<u64> {
+    if let Ok(secs) = s.parse::<u64>() {
+        return Some(secs);
+    }
+    let mut total = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return None,
+        };
+        total = number
+            .parse::<u64>()
+            .ok()?
+            .checked_mul(unit)?
+            .checked_add(total)?;
+        number.clear();
+    }
+    if number.is_empty() && !s.is_empty() {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+pub fn format_duration(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    let mut parts = Vec::new();
+    if h > 0 {
+        parts.push(format!("{}h", h));
+    }
+    if m > 0 {
+        parts.push(format!("{}m", m));
+    }
+    if s > 0 || parts.is_empty() {
+        parts.push(format!("{}s", s));
+    }
+    parts.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("10s"), Some(10));
+        assert_eq!(parse_duration("5m"), Some(300));
+        assert_eq!(parse_duration("1m30s"), Some(90));
+        assert_eq!(parse_duration("2h"), Some(7200));
+        assert_eq!(parse_duration("1d12h"), Some(129_600));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("5"), Some(5));
+        assert_eq!(parse_duration("5m3"), None);
+        assert_eq!(parse_duration("5x"), None);
+        assert_eq!(parse_duration("-5s"), None);
+        assert_eq!(parse_duration("99999999999999999d"), None);
+    }
+
+    #[test]
+    fn formats_durations_that_parse_back() {
+        for secs in &[0, 59, 60, 90, 3600, 3661, 86_400] {
+            assert_eq!(parse_duration(&format_duration(*secs)), Some(*secs));
+        }
+        assert_eq!(format_duration(3661), "1h1m1s");
+        assert_eq!(format_duration(0), "0s");
+    }
+}
@@ -1,45 +1,408 @@
 use crate::backup::*;
+use crate::chat::*;
+use crate::eula::*;
 use crate::io::*;
+use crate::network::PROXY_STOP_COMMAND;
+use crate::performance::*;
+use crate::ping::*;
+use crate::plugins::save_snapshots;
 use crate::processes::*;
+use crate::properties::*;
+use crate::query::*;
+use crate::rcon::*;
 use crate::*;
 
 use std::{
     io::{BufRead, BufReader},
-    process,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
     },
     thread, time,
 };
 
 pub const STOP_COMMAND: &[u8] = b"stop\n";
 pub const SAVE_COMMAND: &[u8] = b"save-all\n";
+pub const SAVED_MESSAGE: &str = "Saved the game";
+
+const SAVE_OFF_COMMAND: &[u8] = b"save-off\n";
+const SAVE_ON_COMMAND: &[u8] = b"save-on\n";
+const SAVE_FLUSH_COMMAND: &[u8] = b"save-all flush\n";
 
 const WAITING_TIME: u64 = 60 * 5;
+const READY_TIME: u64 = 60 * 5;
+const SAVING_TIME: u64 = 60 * 5;
+const QUERY_TIME: u64 = 5;
+const QUERY_QUIET_TIME: u64 = 500;
+//Written once the server has started and runs console commands
+const DONE_MARKER: &str = "]: Done (";
 
 pub struct ServerHandler {
+    channel: ControlChannel,
+    //Properties read when the server was started, updated with the ones applied since then
+    running_properties: Option<ServerProperties>,
+}
+
+/*
+    Servers spawned by the manager are controlled through the pipes of
+    the child process. Servers started by someone else (systemd, k8s...)
+    are controlled through RCON and their lifecycle is not managed
+*/
+enum ControlChannel {
+    Pipe(PipeControl),
+    Rcon(RconClient),
+}
+
+struct PipeControl {
     process_handler: ProcessHandler,
     wanted_dead: Arc<AtomicBool>,
+    eula_required: Arc<AtomicBool>,
+    ready: Arc<AtomicBool>,
     stdin_writer: PipeWriter,
+    stdout_listeners: Arc<Mutex<Vec<Sender<String>>>>,
+    //Lines containing these are answers to the manager and aren't shown
+    hidden_patterns: Arc<Mutex<Vec<String>>>,
     jobs: Vec<thread::JoinHandle<()>>,
+    stop_command: &'static [u8],
 }
 
 impl ServerHandler {
     pub fn start_server(config: &Config) -> GenericResult<Self> {
+        let running_properties = ServerProperties::load(&config.server_path()).ok();
+        let channel = match config.control {
+            ControlMode::Stdin => {
+                save_snapshots(config);
+                let pipe = PipeControl::spawn(config)?;
+                notify_when_ready(&config.server_address);
+                ControlChannel::Pipe(pipe)
+            }
+            ControlMode::Rcon => {
+                let out = get_output_sender();
+                infoln!(
+                    out,
+                    "Connecting to the server through RCON at {}",
+                    config.rcon_address
+                );
+                ControlChannel::Rcon(RconClient::connect(
+                    &config.rcon_address,
+                    &config.rcon_password,
+                )?)
+            }
+        };
+        Ok(Self {
+            channel,
+            running_properties,
+        })
+    }
+
+    pub fn stop_server(self) {
+        match self.channel {
+            ControlChannel::Pipe(pipe) => pipe.stop(),
+            ControlChannel::Rcon(_) => {
+                let out = get_output_sender();
+                warnln!(
+                    out,
+                    "The server was not started by the manager. Leaving it running"
+                );
+            }
+        }
+    }
+
+    //Servers not spawned by the manager are supposed to be running
+    pub fn is_running(&mut self) -> bool {
+        match &mut self.channel {
+            ControlChannel::Pipe(pipe) => !pipe.process_handler.is_dead(),
+            ControlChannel::Rcon(_) => true,
+        }
+    }
+
+    //Servers controlled through RCON only accept connections once they are ready
+    pub fn is_ready(&self) -> bool {
+        match &self.channel {
+            ControlChannel::Pipe(pipe) => pipe.ready.load(Ordering::SeqCst),
+            ControlChannel::Rcon(_) => true,
+        }
+    }
+
+    //True if the server refused to start because the EULA has not been accepted
+    pub fn eula_required(&self) -> bool {
+        match &self.channel {
+            ControlChannel::Pipe(pipe) => pipe.eula_required.load(Ordering::SeqCst),
+            ControlChannel::Rcon(_) => false,
+        }
+    }
+
+    pub fn running_properties(&self) -> Option<&ServerProperties> {
+        self.running_properties.as_ref()
+    }
+
+    pub fn set_running_property(&mut self, key: &str, value: &str) {
+        if let Some(properties) = &mut self.running_properties {
+            properties.set(key, value);
+        }
+    }
+
+    pub fn restart(self, config: &Config) -> GenericResult<Self> {
+        let out = get_output_sender();
+        match self.channel {
+            ControlChannel::Pipe(pipe) => {
+                infoln!(out, "Restarting the server");
+                pipe.stop();
+            }
+            ControlChannel::Rcon(mut rcon) => {
+                //The supervisor is expected to start the server again
+                infoln!(out, "Stopping the server. Waiting for its supervisor to restart it");
+                let _ = rcon.command("stop");
+                let start = time::Instant::now();
+                while ping(&config.server_address).is_ok()
+                    && start.elapsed() < time::Duration::from_secs(WAITING_TIME)
+                {
+                    thread::sleep(time::Duration::from_secs(1));
+                }
+                wait_until_ready(&config.server_address, READY_TIME)?;
+                infoln!(out, "Server is back up");
+            }
+        }
+        Self::start_server(config)
+    }
+
+    pub fn send(&mut self, command: &[u8]) -> GenericResult<()> {
+        match &mut self.channel {
+            ControlChannel::Pipe(pipe) => pipe.stdin_writer.write_all(command),
+            ControlChannel::Rcon(rcon) => {
+                let out = get_output_sender();
+                let commands = String::from_utf8_lossy(command);
+                for c in commands.lines().map(str::trim).filter(|c| !c.is_empty()) {
+                    let response = rcon.command(c)?;
+                    if !response.is_empty() {
+                        rawln!(out, "{}", response);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn sendln(&mut self, command: &[u8]) -> GenericResult<()> {
+        let mut tmp: Vec<u8> = Vec::from(command);
+        tmp.push('\n' as u8);
+        return self.send(&tmp);
+    }
+
+    pub fn status(&mut self, config: &Config) {
+        let out = get_output_sender();
+        match &mut self.channel {
+            ControlChannel::Pipe(pipe) => {
+                let pid = pipe.process_handler.pid();
+                if pipe.process_handler.is_dead() {
+                    warnln!(out, "Server process {} is dead", pid);
+                    return;
+                }
+                infoln!(out, "Server process {} is running", pid);
+            }
+            ControlChannel::Rcon(_) => {
+                infoln!(out, "Server is controlled through RCON");
+            }
+        }
+
+        match ping(&config.server_address) {
+            Ok(status) => {
+                infoln!(
+                    out,
+                    "MOTD: {}\nVersion: {} (protocol {})\nPlayers: {}/{} {}\nLatency: {} ms",
+                    status.motd,
+                    status.version,
+                    status.protocol,
+                    status.online_players,
+                    status.max_players,
+                    status.players_sample.join(", "),
+                    status.latency.as_millis()
+                );
+            }
+            Err(e) => {
+                warnln!(
+                    out,
+                    "Server is not accepting connections at {}: {}",
+                    config.server_address,
+                    e
+                );
+            }
+        }
+
+        //The full stat is only asked for when there are players to list
+        if let Some(query_address) = &config.query_address {
+            let stat = basic_stat(query_address).and_then(|basic| {
+                if basic.online_players == 0 {
+                    return Ok(format!("Map: {}\nNo players online", basic.map));
+                }
+                let full = full_stat(query_address)?;
+                Ok(format!(
+                    "Map: {}\nPlayers: {}\nPlugins: {}",
+                    full.map,
+                    full.players.join(", "),
+                    full.plugins
+                ))
+            });
+            match stat {
+                Ok(s) => {
+                    infoln!(out, "{}", s);
+                }
+                Err(e) => {
+                    warnln!(out, "Could not query the server at {}: {}", query_address, e);
+                }
+            }
+        }
+    }
+
+    //Offline backups stop the server. Servers not spawned by the manager can't be stopped
+    pub fn backup(self, config: &Config) -> GenericResult<Self> {
+        match (self.channel, config.backups_mode) {
+            (ControlChannel::Pipe(pipe), BackupMode::Offline) => {
+                pipe.backup(config);
+                Self::start_server(config)
+            }
+            (channel, _) => {
+                let mut handler = Self {
+                    channel,
+                    running_properties: self.running_properties,
+                };
+                handler.online_backup(config);
+                Ok(handler)
+            }
+        }
+    }
+
+    //Lines written by the server to stdout. Not available for servers controlled through RCON
+    pub fn subscribe_output(&self) -> Option<Receiver<String>> {
+        match &self.channel {
+            ControlChannel::Pipe(pipe) => {
+                let (sender, receiver) = channel();
+                pipe.stdout_listeners.lock().unwrap().push(sender);
+                Some(receiver)
+            }
+            ControlChannel::Rcon(_) => None,
+        }
+    }
+
+    /*
+        Runs a command and returns the lines of the answer containing any of
+        the patterns. Servers spawned by the manager answer through stdout, so
+        those lines are hidden from the output while waiting for them
+    */
+    pub fn query(&mut self, command: &str, patterns: &[&str]) -> GenericResult<String> {
+        let receiver = self.subscribe_output();
+        match &mut self.channel {
+            ControlChannel::Pipe(pipe) => {
+                hide(&pipe.hidden_patterns, patterns);
+                let answer = pipe
+                    .stdin_writer
+                    .write_all(format!("{}\n", command).as_bytes())
+                    .and_then(|_| read_answer(&receiver.unwrap(), patterns));
+                //Late lines of the answer are shown
+                unhide(&pipe.hidden_patterns, patterns);
+                answer
+            }
+            ControlChannel::Rcon(rcon) => {
+                let response = rcon.command(command)?;
+                if patterns.iter().any(|p| response.contains(p)) {
+                    Ok(response)
+                } else {
+                    Err(format!("Unexpected answer to {}: {}", command, response).into())
+                }
+            }
+        }
+    }
+
+    /*
+        Like query, but the answer is waited for in another thread and given
+        to then, so the caller can go on. RCON answers right away
+    */
+    pub fn query_async<F>(&mut self, command: &str, patterns: &[&str], then: F) -> GenericResult<()>
+    where
+        F: FnOnce(GenericResult<String>) + Send + 'static,
+    {
+        let receiver = self.subscribe_output();
+        match &mut self.channel {
+            ControlChannel::Pipe(pipe) => {
+                hide(&pipe.hidden_patterns, patterns);
+                if let Err(e) = pipe.stdin_writer.write_all(format!("{}\n", command).as_bytes()) {
+                    unhide(&pipe.hidden_patterns, patterns);
+                    return Err(e);
+                }
+                let hidden_patterns = pipe.hidden_patterns.clone();
+                let patterns: Vec<String> = patterns.iter().map(|p| String::from(*p)).collect();
+                thread::spawn(move || {
+                    let patterns: Vec<&str> = patterns.iter().map(String::as_str).collect();
+                    let answer = read_answer(&receiver.unwrap(), &patterns);
+                    unhide(&hidden_patterns, &patterns);
+                    then(answer);
+                });
+            }
+            ControlChannel::Rcon(_) => then(self.query(command, patterns)),
+        }
+        Ok(())
+    }
+
+    //The world can't be written while it is being archived, so autosave is disabled meanwhile
+    fn online_backup(&mut self, config: &Config) {
+        let out = get_output_sender();
+
+        infoln!(out, "Disabling autosave and saving the world");
+        match self.save_world() {
+            Ok(()) => {
+                infoln!(out, "Creating backup...");
+                if let Err(e) = backup(config) {
+                    errorln!(out, "Could not make backup. Error: {}", e);
+                };
+            }
+            Err(e) => {
+                errorln!(out, "Could not save the world. Backup cancelled. Error: {}", e);
+            }
+        }
+
+        if let Err(e) = self.send(SAVE_ON_COMMAND) {
+            errorln!(out, "Could not enable autosave again. Error: {}", e);
+        }
+    }
+
+    fn save_world(&mut self) -> GenericResult<()> {
+        let saved = self.subscribe_output();
+        match &mut self.channel {
+            ControlChannel::Pipe(pipe) => {
+                pipe.stdin_writer.write_all(SAVE_OFF_COMMAND)?;
+                pipe.stdin_writer.write_all(SAVE_FLUSH_COMMAND)?;
+                wait_for_line(&saved.unwrap(), SAVED_MESSAGE, SAVING_TIME)?;
+            }
+            //Commands sent through RCON are answered once they have finished
+            ControlChannel::Rcon(rcon) => {
+                rcon.command("save-off")?;
+                let response = rcon.command("save-all flush")?;
+                if !response.contains(SAVED_MESSAGE) {
+                    return Err(format!("Unexpected answer to save-all: {}", response).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PipeControl {
+    fn spawn(config: &Config) -> GenericResult<Self> {
         let wanted_dead = Arc::new(AtomicBool::new(false));
         let wanted_dead_c = wanted_dead.clone();
 
+        let eula_required = Arc::new(AtomicBool::new(false));
+        let eula_required_c = eula_required.clone();
+
+        let ready = Arc::new(AtomicBool::new(false));
+        let ready_c = ready.clone();
+
+        //The main loop decides what to do with the dead server
+        let input = get_input_sender();
         let shutdown_clos = move || {
-            let out = get_output_sender();
             if !wanted_dead_c.load(std::sync::atomic::Ordering::SeqCst) {
-                errorln!(out, "Server went brrr");
-                warnln!(
-                    out,
-                    "Some threads could panic as the process is gonna be closed without waiting"
-                );
-                thread::sleep(time::Duration::from_millis(100));
-                process::exit(-1);
+                input.send(InputPacket::ServerDied).unwrap();
             }
         };
 
@@ -54,29 +417,58 @@ impl ServerHandler {
         )?;
 
         let stdin_writer = process_handler.get_stdin_writer();
+        let stdout_listeners: Arc<Mutex<Vec<Sender<String>>>> = Arc::new(Mutex::new(Vec::new()));
+        let stdout_listeners_c = stdout_listeners.clone();
+        let hidden_patterns: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let hidden_patterns_c = hidden_patterns.clone();
         let mut stdout_reader = BufReader::new(process_handler.get_stdout_reader());
         let mut stderr_reader = BufReader::new(process_handler.get_stderr_reader());
+        let mut chat_parser = config.telegram_chat_id.map(|_| ChatParser::new());
 
+        let out = get_output_sender();
+        let input = get_input_sender();
         let stdout_clos = move || {
-            let out = get_output_sender();
             let mut buf = String::new();
             loop {
                 if let Err(_e) = stdout_reader.read_line(&mut buf) {
                     break;
                 }
-                raw!(out, "{}", buf);
+                let hidden = hidden_patterns_c
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|p| buf.contains(p.as_str()));
+                if !hidden {
+                    raw!(out, "{}", buf);
+                }
+                if buf.contains(EULA_LOG_LINE) {
+                    eula_required_c.store(true, Ordering::SeqCst);
+                }
+                if buf.contains(DONE_MARKER) {
+                    ready_c.store(true, Ordering::SeqCst);
+                }
+                if let Some((ms, ticks)) = parse_lag_line(&buf) {
+                    let _ = input.send(InputPacket::Lag { ms, ticks });
+                }
+                if let Some(event) = chat_parser.as_mut().and_then(|p| p.parse(&buf)) {
+                    let _ = out.send(OutputPacket::Chat(event.format()));
+                }
+                stdout_listeners_c
+                    .lock()
+                    .unwrap()
+                    .retain(|l| l.send(buf.clone()).is_ok());
                 buf.clear();
             }
         };
 
+        let err_out = get_output_sender();
         let stderr_clos = move || {
-            let out = get_output_sender();
             let mut buf = String::new();
             loop {
                 if let Err(_e) = stderr_reader.read_line(&mut buf) {
                     break;
                 }
-                raw!(out, "{}", buf);
+                raw!(err_out, "{}", buf);
                 buf.clear();
             }
         };
@@ -90,15 +482,23 @@ impl ServerHandler {
         Ok(Self {
             process_handler,
             wanted_dead,
+            eula_required,
+            ready,
             stdin_writer,
+            stdout_listeners,
+            hidden_patterns,
             jobs,
+            stop_command: match config.role {
+                ServerRole::Proxy => PROXY_STOP_COMMAND,
+                ServerRole::Backend => STOP_COMMAND,
+            },
         })
     }
 
-    pub fn stop_server(mut self) {
+    fn stop(mut self) {
         let out = get_output_sender();
         self.wanted_dead.store(true, Ordering::SeqCst);
-        if let Err(e) = self.stdin_writer.write_all(STOP_COMMAND) {
+        if let Err(e) = self.stdin_writer.write_all(self.stop_command) {
             if self.process_handler.is_dead() {
                 warnln!(out, "Server is already dead!");
             } else {
@@ -121,17 +521,7 @@ impl ServerHandler {
         }
     }
 
-    pub fn send(&mut self, command: &[u8]) -> GenericResult<()> {
-        return self.stdin_writer.write_all(command);
-    }
-
-    pub fn sendln(&mut self, command: &[u8]) -> GenericResult<()> {
-        let mut tmp: Vec<u8> = Vec::from(command);
-        tmp.push('\n' as u8);
-        return self.send(&tmp);
-    }
-
-    pub fn backup(mut self, config: &Config) {
+    fn backup(mut self, config: &Config) {
         let out = get_output_sender();
 
         infoln!(out, "Saving and closing the server");
@@ -165,3 +555,81 @@ impl ServerHandler {
         };
     }
 }
+
+//Several answers can be waited for at once, so each one only removes its own patterns
+fn hide(hidden_patterns: &Mutex<Vec<String>>, patterns: &[&str]) {
+    hidden_patterns
+        .lock()
+        .unwrap()
+        .extend(patterns.iter().map(|p| String::from(*p)));
+}
+
+fn unhide(hidden_patterns: &Mutex<Vec<String>>, patterns: &[&str]) {
+    let mut hidden = hidden_patterns.lock().unwrap();
+    for p in patterns {
+        if let Some(i) = hidden.iter().position(|h| h == p) {
+            hidden.remove(i);
+        }
+    }
+}
+
+pub fn wait_for_line(receiver: &Receiver<String>, pattern: &str, timeout: u64) -> GenericResult<String> {
+    let deadline = time::Instant::now() + time::Duration::from_secs(timeout);
+    loop {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        match receiver.recv_timeout(remaining) {
+            Ok(line) if line.contains(pattern) => return Ok(line),
+            Ok(_) => (),
+            Err(_) => {
+                return Err(format!(
+                    "Server didn't write \"{}\" after {} seconds",
+                    pattern, timeout
+                )
+                .into())
+            }
+        }
+    }
+}
+
+//Lines matching the patterns, from the first one until the server is quiet
+fn read_answer(receiver: &Receiver<String>, patterns: &[&str]) -> GenericResult<String> {
+    let matches = |line: &String| patterns.iter().any(|p| line.contains(p));
+    let mut answer = String::new();
+    let deadline = time::Instant::now() + time::Duration::from_secs(QUERY_TIME);
+    loop {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        match receiver.recv_timeout(remaining) {
+            Ok(line) if matches(&line) => {
+                answer.push_str(&line);
+                break;
+            }
+            Ok(_) => (),
+            Err(_) => return Err("The server didn't answer".into()),
+        }
+    }
+    while let Ok(line) = receiver.recv_timeout(time::Duration::from_millis(QUERY_QUIET_TIME)) {
+        if matches(&line) {
+            answer.push_str(&line);
+        }
+    }
+    Ok(answer)
+}
+
+fn notify_when_ready(address: &str) {
+    let address = String::from(address);
+    let out = get_output_sender();
+    thread::spawn(move || {
+        match wait_until_ready(&address, READY_TIME) {
+            Ok(status) => {
+                infoln!(
+                    out,
+                    "Server is ready and accepting connections ({})",
+                    status.version
+                );
+            }
+            Err(e) => {
+                warnln!(out, "{}", e);
+            }
+        }
+    });
+}
@@ -1,4 +1,6 @@
 use crate::error::*;
+use std::{fs, path::Path};
+use tbot::types::input_file::Document;
 use tokio::runtime::{Builder, Runtime};
 
 pub struct TelegramMessageSender {
@@ -20,8 +22,27 @@ impl TelegramMessageSender {
     }
 
     pub fn send_message(&mut self, message: &str) -> GenericResult<()> {
+        self.send_message_to(self.user.0, message)
+    }
+
+    pub fn send_message_to(&mut self, chat: i64, message: &str) -> GenericResult<()> {
+        self.rt.block_on(
+            self.bot
+                .send_message(tbot::types::chat::Id(chat), message)
+                .call(),
+        )?;
+        Ok(())
+    }
+
+    pub fn send_file(&mut self, path: &Path, caption: &str) -> GenericResult<()> {
+        let bytes = fs::read(path)?;
+        let filename = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let document = Document::with_bytes(&filename, &bytes).caption(caption);
         self.rt
-            .block_on(self.bot.send_message(self.user, message).call())?;
+            .block_on(self.bot.send_document(self.user, document).call())?;
         Ok(())
     }
 }
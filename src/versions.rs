@@ -0,0 +1,263 @@
+use crate::backup::*;
+use crate::io::*;
+use crate::ping::ping;
+use crate::server_handler::ServerHandler;
+use crate::*;
+
+use serde_json::Value;
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+use zip::ZipArchive;
+
+const VERSIONS_DIRECTORY: &str = "versions";
+//Name of the version chosen with the last upgrade
+const ACTIVE_VERSION_FILE: &str = "active";
+const JAR_EXTENSION: &str = "jar";
+const UPGRADE_READY_TIME: u64 = 60 * 5;
+//Written by Mojang in the server jars since 1.14
+const VERSION_FILE: &str = "version.json";
+
+pub fn versions_directory(config: &Config) -> PathBuf {
+    config
+        .versions_directory
+        .clone()
+        .unwrap_or_else(|| config.server_path().join(VERSIONS_DIRECTORY))
+}
+
+pub fn list_versions(config: &Config) -> GenericResult<Vec<String>> {
+    let mut versions: Vec<String> = fs::read_dir(versions_directory(config))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().map(|e| e == JAR_EXTENSION).unwrap_or(false))
+        .filter_map(|p| Some(p.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+    versions.sort();
+    Ok(versions)
+}
+
+pub fn find_version(config: &Config, version: &str) -> GenericResult<PathBuf> {
+    let jar = versions_directory(config).join(format!("{}.{}", version, JAR_EXTENSION));
+    if jar.is_file() {
+        Ok(jar)
+    } else {
+        Err(format!("There is no {}", jar.display()).into())
+    }
+}
+
+//The jar is the argument after -jar or, if there is none, the first one ending in .jar
+fn jar_arg_index(args: &[CString]) -> Option<usize> {
+    let args: Vec<String> = args
+        .iter()
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect();
+    match args.iter().position(|a| a == "-jar") {
+        Some(i) if i + 1 < args.len() => Some(i + 1),
+        _ => args.iter().position(|a| a.ends_with(".jar")),
+    }
+}
+
+//Paths are compared and written as absolute ones, as they can be written in different ways
+fn canonical(path: PathBuf) -> PathBuf {
+    fs::canonicalize(&path).unwrap_or(path)
+}
+
+//The server is started in its directory, so a relative jar is relative to it
+pub fn active_jar(config: &Config) -> Option<PathBuf> {
+    let jar = PathBuf::from(config.args[jar_arg_index(&config.args)?].to_string_lossy().into_owned());
+    Some(canonical(config.server_path().join(jar)))
+}
+
+//version.json of the active jar. Jars of other flavors may not have it
+pub fn jar_version_info(config: &Config) -> Option<Value> {
+    let jar = active_jar(config)?;
+    let mut archive = ZipArchive::new(File::open(jar).ok()?).ok()?;
+    let mut entry = archive.by_name(VERSION_FILE).ok()?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+//Newest world data version the active jar can load
+pub fn server_data_version(config: &Config) -> Option<i64> {
+    jar_version_info(config)?["world_version"].as_i64()
+}
+
+fn set_jar(config: &mut Config, jar: &Path) -> GenericResult<()> {
+    let i = jar_arg_index(&config.args).ok_or("There is no jar in the launch arguments")?;
+    let jar = canonical(jar.to_path_buf());
+    config.args[i] = CString::new(jar.to_string_lossy().into_owned())
+        .map_err(|_| GenericError::from("Invalid jar path"))?;
+    Ok(())
+}
+
+//Applies the version chosen with the last upgrade, if any
+pub fn load_active_version(config: &mut Config) -> GenericResult<()> {
+    let file = versions_directory(config).join(ACTIVE_VERSION_FILE);
+    if !file.exists() {
+        return Ok(());
+    }
+    let version = fs::read_to_string(file)?;
+    let jar = find_version(config, version.trim())?;
+    set_jar(config, &jar)
+}
+
+fn save_active_version(config: &Config, version: &str) -> GenericResult<()> {
+    fs::write(versions_directory(config).join(ACTIVE_VERSION_FILE), version)?;
+    Ok(())
+}
+
+//versions: shows the available server jars and the active one
+pub fn versions_command(config: &Config) {
+    let out = get_output_sender();
+    let active = active_jar(config);
+    match list_versions(config) {
+        Ok(versions) if versions.is_empty() => {
+            warnln!(
+                out,
+                "There are no jars in {}",
+                versions_directory(config).display()
+            );
+        }
+        Ok(versions) => {
+            let versions: Vec<String> = versions
+                .into_iter()
+                .map(|v| match (&active, find_version(config, &v)) {
+                    (Some(a), Ok(jar)) if *a == canonical(jar.clone()) => format!("{} (active)", v),
+                    _ => v,
+                })
+                .collect();
+            infoln!(out, "Versions: {}", versions.join(", "));
+        }
+        Err(e) => {
+            errorln!(
+                out,
+                "Could not read {}: {}",
+                versions_directory(config).display(),
+                e
+            );
+        }
+    }
+    if let Some(jar) = active {
+        infoln!(out, "Active jar: {}", jar.display());
+    }
+}
+
+/*
+    The server is stopped and backed up before switching the jar. If the
+    new version doesn't become ready, the world and the previous jar are
+    brought back. If that fails too, the server is left stopped and the
+    user is told which jar and world it has
+*/
+pub fn upgrade(server: ServerHandler, version: &str, config: &mut Config) -> Option<ServerHandler> {
+    let out = get_output_sender();
+    let (jar, previous) = match (find_version(config, version), active_jar(config)) {
+        (Ok(jar), Some(previous)) => (jar, previous),
+        (Err(e), _) => {
+            errorln!(out, "{}", e);
+            return Some(server);
+        }
+        (_, None) => {
+            errorln!(out, "There is no jar in the launch arguments");
+            return Some(server);
+        }
+    };
+
+    infoln!(out, "Stopping the server to upgrade it to {}", version);
+    server.stop_server();
+    infoln!(out, "Creating backup...");
+    let backup_path = match backup(config) {
+        Ok(path) => path,
+        Err(e) => {
+            errorln!(out, "Could not make backup. Upgrade cancelled. Error: {}", e);
+            return start(config);
+        }
+    };
+
+    let error = match set_jar(config, &jar).and_then(|_| ServerHandler::start_server(config)) {
+        Ok(mut server) => match wait_ready(&mut server, config) {
+            Ok(()) => {
+                if let Err(e) = save_active_version(config, version) {
+                    warnln!(
+                        out,
+                        "Could not save {} as the active version. The previous one will be used when the manager starts again: {}",
+                        version,
+                        e
+                    );
+                }
+                infoln!(out, "Server upgraded to {}", version);
+                return Some(server);
+            }
+            Err(e) => {
+                server.stop_server();
+                e
+            }
+        },
+        Err(e) => e,
+    };
+
+    errorln!(
+        out,
+        "The server didn't start with {}: {}. Rolling back to {}",
+        version,
+        error,
+        previous.display()
+    );
+    if let Err(e) = set_jar(config, &previous) {
+        errorln!(
+            out,
+            "Could not go back to {}: {}. The server is stopped with {} and the world as {} left it. The backup is {}",
+            previous.display(),
+            e,
+            jar.display(),
+            version,
+            backup_path.display()
+        );
+        return None;
+    }
+    if let Err(e) = restore(config, &backup_path) {
+        errorln!(
+            out,
+            "Could not restore the world from {}: {}. The server is stopped with {} and the world as {} left it",
+            backup_path.display(),
+            e,
+            previous.display(),
+            version
+        );
+        return None;
+    }
+    infoln!(out, "World restored from {}", backup_path.display());
+    start(config)
+}
+
+//A server that doesn't start is left stopped, so it can be fixed and started again
+fn start(config: &Config) -> Option<ServerHandler> {
+    match ServerHandler::start_server(config) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            let out = get_output_sender();
+            errorln!(out, "Could not start the server: {}. Use the start command to try again", e);
+            None
+        }
+    }
+}
+
+//Fails as soon as the server dies
+fn wait_ready(server: &mut ServerHandler, config: &Config) -> GenericResult<()> {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(UPGRADE_READY_TIME) {
+        if !server.is_running() {
+            return Err("The server died while starting".into());
+        }
+        if ping(&config.server_address).is_ok() {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    Err(format!("The server wasn't ready after {} seconds", UPGRADE_READY_TIME).into())
+}
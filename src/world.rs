@@ -0,0 +1,280 @@
+use crate::backup::compress_directory;
+use crate::io::*;
+use crate::nbt::*;
+use crate::properties::*;
+use crate::server_handler::ServerHandler;
+use crate::*;
+
+use chrono::Local;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const LEVEL_FILE: &str = "level.dat";
+
+const DIFFICULTIES: [&str; 4] = ["peaceful", "easy", "normal", "hard"];
+const TICKS_PER_DAY: i64 = 24000;
+//Bukkit servers keep the other dimensions in their own worlds
+const DIMENSION_SUFFIXES: [&str; 2] = ["_nether", "_the_end"];
+
+pub struct LevelData {
+    pub name: Option<String>,
+    pub seed: Option<i64>,
+    pub version: Option<String>,
+    pub data_version: Option<i64>,
+    pub difficulty: Option<i64>,
+    pub hardcore: bool,
+    pub spawn: Option<(i64, i64, i64)>,
+    pub game_time: i64,
+    pub day_time: i64,
+    pub raining: bool,
+    pub thundering: bool,
+}
+
+impl LevelData {
+    //world is the directory of the world
+    pub fn load(world: &Path) -> GenericResult<LevelData> {
+        let root = read_gzip_nbt(&world.join(LEVEL_FILE))?;
+        let data = root.get("Data").ok_or("level.dat has no Data")?;
+        let int = |key: &str| data.get(key).and_then(Tag::as_i64);
+
+        //The seed was moved to the world generation settings in 1.16
+        let seed = data
+            .get_path(&["WorldGenSettings", "seed"])
+            .or_else(|| data.get("RandomSeed"))
+            .and_then(Tag::as_i64);
+        //And the spawn point to its own compound in 1.21.5
+        let spawn = match data.get_path(&["spawn", "pos"]).and_then(Tag::as_int_array) {
+            Some([x, y, z]) => Some((*x as i64, *y as i64, *z as i64)),
+            _ => match (int("SpawnX"), int("SpawnY"), int("SpawnZ")) {
+                (Some(x), Some(y), Some(z)) => Some((x, y, z)),
+                _ => None,
+            },
+        };
+
+        Ok(LevelData {
+            name: data.get("LevelName").and_then(Tag::as_str).map(String::from),
+            seed,
+            version: data
+                .get_path(&["Version", "Name"])
+                .and_then(Tag::as_str)
+                .map(String::from),
+            data_version: int("DataVersion"),
+            difficulty: int("Difficulty"),
+            hardcore: data.get("hardcore").and_then(Tag::as_bool).unwrap_or(false),
+            spawn,
+            game_time: int("Time").unwrap_or(0),
+            day_time: int("DayTime").unwrap_or(0),
+            raining: data.get("raining").and_then(Tag::as_bool).unwrap_or(false),
+            thundering: data.get("thundering").and_then(Tag::as_bool).unwrap_or(false),
+        })
+    }
+
+    pub fn weather(&self) -> &'static str {
+        match (self.raining, self.thundering) {
+            (_, true) => "thunder",
+            (true, false) => "rain",
+            _ => "clear",
+        }
+    }
+}
+
+fn show_info(world: &Path) -> GenericResult<()> {
+    let out = get_output_sender();
+    let level = LevelData::load(world)?;
+    let unknown = || String::from("unknown");
+    infoln!(
+        out,
+        "World: {}\nSeed: {}\nVersion: {} (data version {})\nDifficulty: {}{}\nSpawn: {}\nGame time: {} ticks (day {})\nDay time: {}\nWeather: {}",
+        level.name.clone().unwrap_or_else(|| world.display().to_string()),
+        level.seed.map(|s| s.to_string()).unwrap_or_else(unknown),
+        level.version.clone().unwrap_or_else(unknown),
+        level.data_version.map(|v| v.to_string()).unwrap_or_else(unknown),
+        level
+            .difficulty
+            .and_then(|d| DIFFICULTIES.get(d as usize))
+            .map(|d| d.to_string())
+            .unwrap_or_else(unknown),
+        if level.hardcore { " (hardcore)" } else { "" },
+        level
+            .spawn
+            .map(|(x, y, z)| format!("{} {} {}", x, y, z))
+            .unwrap_or_else(unknown),
+        level.game_time,
+        level.game_time / TICKS_PER_DAY,
+        level.day_time % TICKS_PER_DAY,
+        level.weather()
+    );
+    Ok(())
+}
+
+//Directories of the server directory with a level.dat
+pub fn list_worlds(config: &Config) -> GenericResult<Vec<String>> {
+    let mut worlds: Vec<String> = fs::read_dir(config.server_path())?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().join(LEVEL_FILE).is_file())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    worlds.sort();
+    Ok(worlds)
+}
+
+//The world in server.properties and, if it's different, the one the server is running
+fn worlds_in_use(config: &Config, server: Option<&ServerHandler>) -> Vec<String> {
+    let mut in_use = Vec::new();
+    if let Some(name) = world_path(config).file_name() {
+        in_use.push(name.to_string_lossy().into_owned());
+    }
+    if let Some(name) = server
+        .and_then(|s| s.running_properties())
+        .and_then(|p| p.get("level-name"))
+    {
+        in_use.push(String::from(name));
+    }
+    in_use
+}
+
+fn check_name(name: &str) -> GenericResult<()> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        Err(format!("Invalid world name: {}", name).into())
+    } else {
+        Ok(())
+    }
+}
+
+fn set_properties(config: &Config, values: &[(&str, &str)]) -> GenericResult<()> {
+    let mut properties = ServerProperties::load(&config.server_path())?;
+    for (key, value) in values {
+        properties.set(key, value);
+    }
+    properties.save()
+}
+
+fn show_list(config: &Config, server: Option<&ServerHandler>) -> GenericResult<()> {
+    let out = get_output_sender();
+    let in_use = worlds_in_use(config, server);
+    let worlds: Vec<String> = list_worlds(config)?
+        .into_iter()
+        .map(|w| {
+            if in_use.contains(&w) {
+                format!("{} (active)", w)
+            } else {
+                w
+            }
+        })
+        .collect();
+    infoln!(out, "Worlds: {}", worlds.join(", "));
+    Ok(())
+}
+
+//Every directory of the world is compressed to the backups directory and removed
+fn archive(config: &Config, name: &str) -> GenericResult<Vec<PathBuf>> {
+    let server_path = config.server_path();
+    let date = Local::now().format("%Y-%m-%d").to_string();
+    let mut archived = Vec::new();
+    let directories = std::iter::once(String::from(name))
+        .chain(DIMENSION_SUFFIXES.iter().map(|s| format!("{}{}", name, s)));
+    for directory in directories {
+        let origin = server_path.join(&directory);
+        if !origin.is_dir() {
+            continue;
+        }
+        let destination = config
+            .backups_directory
+            .join(format!("{}_{}.tar.gz", directory, date));
+        compress_directory(&origin, &destination)?;
+        fs::remove_dir_all(&origin)?;
+        archived.push(destination);
+    }
+    Ok(archived)
+}
+
+//The server is restarted to load the new world, if it is running
+fn restart(server: Option<ServerHandler>, config: &Config) -> GenericResult<Option<ServerHandler>> {
+    match server {
+        Some(server) => server.restart(config).map(Some),
+        None => Ok(None),
+    }
+}
+
+/*
+    world info: shows the information in level.dat
+    world list: shows the worlds in the server directory
+    world switch <name>: changes level-name and restarts the server
+    world create <name> [seed] [level type]: same, for a world the server will generate
+    world archive <name>: compresses a world to the backups directory and removes it
+
+    The server writes level.dat when it saves the world, so
+    it can be a few minutes old. Returns an error if the server
+    could not be restarted
+*/
+pub fn world_command(
+    args: &[&str],
+    config: &Config,
+    server: Option<ServerHandler>,
+) -> GenericResult<Option<ServerHandler>> {
+    let out = get_output_sender();
+    let in_use = worlds_in_use(config, server.as_ref());
+
+    //Whether the server has to be restarted
+    let result: GenericResult<bool> = match args {
+        ["info"] => show_info(&world_path(config)).map(|_| false),
+        ["list"] => show_list(config, server.as_ref()).map(|_| false),
+        ["switch", name] | ["create", name, ..] | ["archive", name] if in_use.iter().any(|w| w == name) => {
+            Err(format!("{} is the world in use", name).into())
+        }
+        ["switch", name] => check_name(name)
+            .and_then(|_| match list_worlds(config)?.iter().any(|w| w == name) {
+                true => set_properties(config, &[("level-name", name)]),
+                false => Err(format!("There is no world called {}", name).into()),
+            })
+            .map(|_| {
+                infoln!(out, "Switched to {}", name);
+                true
+            }),
+        ["create", name, options @ ..] if options.len() <= 2 => check_name(name)
+            .and_then(|_| match config.server_path().join(name).exists() {
+                true => Err(format!("{} already exists", name).into()),
+                false => set_properties(
+                    config,
+                    &[
+                        ("level-name", name),
+                        ("level-seed", options.first().unwrap_or(&"")),
+                        ("level-type", options.get(1).unwrap_or(&"minecraft:normal")),
+                    ],
+                ),
+            })
+            .map(|_| {
+                infoln!(out, "{} will be generated when the server starts", name);
+                true
+            }),
+        ["archive", name] => check_name(name)
+            .and_then(|_| match list_worlds(config)?.iter().any(|w| w == name) {
+                true => archive(config, name),
+                false => Err(format!("There is no world called {}", name).into()),
+            })
+            .map(|archives| {
+                for a in archives {
+                    infoln!(out, "Archived to {}", a.display());
+                }
+                false
+            }),
+        _ => {
+            warnln!(
+                out,
+                "Usage: world info | world list | world switch <name> | world create <name> [seed] [level type] | world archive <name>"
+            );
+            Ok(false)
+        }
+    };
+
+    match result {
+        Ok(true) => restart(server, config),
+        Ok(false) => Ok(server),
+        Err(e) => {
+            errorln!(out, "{}", e);
+            Ok(server)
+        }
+    }
+}